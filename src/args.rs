@@ -0,0 +1,113 @@
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Command-line arguments
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    /// Display the version of the program
+    pub version: bool,
+    /// Display the help message
+    pub help: bool,
+    /// Print the JSON Schema of the messages
+    pub schema: bool,
+    /// Print the effective configuration
+    pub print_config: bool,
+    /// Additional configuration file
+    pub config: Option<PathBuf>,
+    /// Configuration values set on the command line, as `section.key` and value
+    pub overrides: Vec<(String, String)>,
+    /// Subcommand to run instead of connecting
+    pub command: Option<Command>,
+}
+
+/// Subcommand
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Manages the UUID identifying the client
+    Identity(IdentityCommand),
+    /// Manages the configuration files
+    Config(ConfigCommand),
+}
+
+/// Configuration subcommand
+#[derive(Debug, PartialEq)]
+pub enum ConfigCommand {
+    /// Validates the configuration files
+    Check,
+}
+
+/// Identity subcommand
+#[derive(Debug, PartialEq)]
+pub enum IdentityCommand {
+    /// Shows the UUID and where it is stored
+    Show,
+    /// Replaces the UUID with a new one
+    Regenerate,
+    /// Writes the UUID to a file
+    Export(PathBuf),
+    /// Replaces the UUID with the one of a file
+    Import(PathBuf),
+}
+
+impl Args {
+    /// Parses the arguments, without the program name
+    ///
+    /// Options taking a value accept both `--option value` and `--option=value`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (option, inline) = match arg.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => {
+                    (option.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .with_context(|| format!("Missing value for {option}"))
+            };
+
+            match option.as_str() {
+                "-v" | "--version" => parsed.version = true,
+                "-h" | "--help" => parsed.help = true,
+                "--schema" => parsed.schema = true,
+                "--print-config" => parsed.print_config = true,
+                "--config" => parsed.config = Some(value()?.into()),
+                "--url" => parsed.overrides.push(("url".to_string(), value()?)),
+                "--proxy" => parsed.overrides.push(("proxy".to_string(), value()?)),
+                "--profile" => parsed.overrides.push(("profile".to_string(), value()?)),
+                "--set" => {
+                    let setting = value()?;
+                    let (key, value) = setting
+                        .split_once('=')
+                        .with_context(|| format!("Expected key=value for --set: {setting}"))?;
+                    parsed
+                        .overrides
+                        .push((key.trim().to_string(), value.trim().to_string()));
+                }
+                _ if !option.starts_with('-') => positional.push(option),
+                _ => bail!("Unknown option: {option} (see --help)"),
+            }
+        }
+
+        let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+        parsed.command = match positional[..] {
+            [] => None,
+            ["identity"] | ["identity", "show"] => Some(Command::Identity(IdentityCommand::Show)),
+            ["identity", "regenerate"] => Some(Command::Identity(IdentityCommand::Regenerate)),
+            ["identity", "export", path] => {
+                Some(Command::Identity(IdentityCommand::Export(path.into())))
+            }
+            ["identity", "import", path] => {
+                Some(Command::Identity(IdentityCommand::Import(path.into())))
+            }
+            ["config", "check"] => Some(Command::Config(ConfigCommand::Check)),
+            _ => bail!("Unknown command: {} (see --help)", positional.join(" ")),
+        };
+        Ok(parsed)
+    }
+}
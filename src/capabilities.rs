@@ -0,0 +1,75 @@
+use std::collections::BTreeSet;
+
+use crate::{models::ClientCmd, VERSION};
+
+/// Version of the message protocol spoken by this client
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Server commands understood by this client
+pub const COMMANDS: &[&str] = &[
+    "hello", "message", "game", "link", "kick", "players", "status", "exit",
+];
+
+/// Optional features supported by this client
+pub const FEATURES: &[&str] = &["latency", "offline", "sync", "events"];
+
+/// Builds the hello sent as the first message of every connection
+pub fn hello() -> ClientCmd {
+    ClientCmd::Hello {
+        protocol: PROTOCOL_VERSION,
+        version: VERSION.to_string(),
+        commands: COMMANDS.iter().map(|s| s.to_string()).collect(),
+        os: os(),
+        locale: sys_locale::get_locale().unwrap_or_else(|| "en-US".to_string()),
+        features: FEATURES.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+/// Returns the operating system and architecture of the host
+pub fn os() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Capabilities advertised by the server
+///
+/// Servers that predate the handshake never answer the hello and keep the default,
+/// which supports nothing beyond the original commands.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// Protocol version of the server (0 until the server answers)
+    pub protocol: u32,
+    /// Client commands understood by the server
+    pub commands: BTreeSet<String>,
+    /// Optional features enabled by the server
+    pub features: BTreeSet<String>,
+}
+
+impl ServerCapabilities {
+    /// Returns whether the server understands the client command
+    pub fn supports_command(&self, cmd: &str) -> bool {
+        self.commands.contains(cmd)
+    }
+
+    /// Returns whether the feature is enabled on both sides
+    pub fn supports_feature(&self, feature: &str) -> bool {
+        FEATURES.contains(&feature) && self.features.contains(feature)
+    }
+
+    /// Returns whether the server agreed to receive the message
+    ///
+    /// Answers to the server requests are always accepted. Other messages need their feature,
+    /// and a server listing its commands only receives those.
+    pub fn accepts(&self, cmd: &ClientCmd) -> bool {
+        let (command, feature) = match cmd {
+            ClientCmd::Sync { .. } => ("sync", "sync"),
+            ClientCmd::Latency { .. } => ("latency", "latency"),
+            ClientCmd::Offline { .. } => ("offline", "offline"),
+            ClientCmd::PlayerJoined { .. } => ("player_joined", "events"),
+            ClientCmd::PlayerLeft { .. } => ("player_left", "events"),
+            ClientCmd::GameChanged { .. } => ("game_changed", "events"),
+            _ => return true,
+        };
+        self.supports_feature(feature)
+            && (self.commands.is_empty() || self.supports_command(command))
+    }
+}
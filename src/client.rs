@@ -0,0 +1,400 @@
+use anyhow::{anyhow, Context as _, Result};
+use futures_util::stream::StreamExt;
+use std::{future::Future, sync::Arc};
+use tokio::{
+    sync::{mpsc, watch, Semaphore},
+    task::{self, JoinHandle},
+    time::{self, timeout, Duration, Instant},
+};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{
+        http::{uri::Builder, Uri},
+        protocol::{frame::coding::CloseCode, CloseFrame, Message},
+        Error as WsError,
+    },
+    Connector,
+};
+
+use crate::{
+    capabilities,
+    config::EndpointConfig,
+    console,
+    handlers::Handler,
+    heartbeat::Heartbeat,
+    models::{ClientCmd, ClientMessage, ErrorStatus, ServerCmd, ServerMessage},
+    proxy::{self, Proxy},
+    retry::Backoff,
+    tls,
+    writer::{self, Outbox},
+    ws_error_handler::handle_ws_error,
+    VERSION,
+};
+
+/// Seconds without any message from the server before the connection is considered dead
+const READ_TIMEOUT: u64 = 60;
+
+/// Reason the client stopped
+#[derive(Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// A shutdown signal was received
+    Signal,
+    /// The server requested the exit
+    Server,
+    /// The client stopped reconnecting
+    Disconnected,
+}
+
+/// Builds the WebSocket URL for the endpoint
+pub fn build_url(endpoint_url: &str, token: &str, session_id: u32) -> Result<String> {
+    let uri: Uri = endpoint_url.parse().context("Failed to parse URL")?;
+    let uri = Builder::from(uri)
+        .path_and_query(format!(
+            "/ws?v={VERSION}&token={token}&session={session_id}"
+        ))
+        .build()
+        .context("Failed to build URL")?;
+    Ok(uri.to_string())
+}
+
+/// Where and how to connect to the server
+#[derive(Clone, Default)]
+pub struct Endpoint {
+    /// Complete WebSocket URL, as built by `build_url`
+    pub url: String,
+    /// Proxy to connect through
+    pub proxy: Option<Proxy>,
+    /// TLS connector (the default one if not set)
+    pub tls: Option<Connector>,
+}
+
+impl Endpoint {
+    /// Builds the endpoint from the configuration, identifying with the token or else the UUID
+    pub fn new(config: &EndpointConfig, uuid: &str, session_id: u32) -> Result<Self> {
+        let token = config.token.as_deref().unwrap_or(uuid);
+        Ok(Self {
+            url: build_url(&config.url, token, session_id)?,
+            proxy: Proxy::resolve(config.proxy.as_deref(), &config.url)?,
+            tls: tls::connector(&config.tls)?,
+        })
+    }
+}
+
+/// Connects to the server and processes messages, reconnecting when the connection is lost
+///
+/// Returns when the client should exit, either on request or once `shutdown` completes.
+/// When the endpoint changes, the client closes the connection and connects to the new one.
+pub async fn run(
+    mut endpoints: watch::Receiver<Endpoint>,
+    handler: Arc<Handler>,
+    shutdown: impl Future<Output = &'static str>,
+) -> Result<Exit> {
+    tokio::pin!(shutdown);
+    // Reconnection flag
+    let mut reconnect = false;
+    // URL of the last successful connection, whose session a reconnection resumes
+    let mut connected_url: Option<String> = None;
+    // Reconnection backoff
+    let mut backoff = Backoff::new(handler.config().retry);
+
+    loop {
+        // Whether the connection was closed to switch to a new endpoint
+        let mut switched = false;
+        let result: Result<()> = try {
+            // Display the reconnection message
+            if reconnect {
+                console::println!("↪ Reconnecting to the server...");
+            }
+
+            // Settings of this connection
+            let endpoint = endpoints.borrow_and_update().clone();
+            let config = handler.config();
+            // Requests being processed
+            let in_flight = Arc::new(Semaphore::new(config.requests.max_in_flight.max(1)));
+
+            // Create a WebSocket client, through the proxy if any
+            let connect = async {
+                let url = endpoint.url.as_str();
+                let tls = endpoint.tls.clone();
+                match &endpoint.proxy {
+                    Some(proxy) => proxy::connect_async(url, proxy, tls).await,
+                    None => connect_async_tls_with_config(url, None, false, tls).await,
+                }
+            };
+            let connect_result = tokio::select! {
+                result = timeout(Duration::from_secs(10), connect) => {
+                    result.context("Connection timed out to the server")?
+                }
+                signal = &mut shutdown => {
+                    console::println!("↪ Shutting down ({signal})...");
+                    handler.disconnect_guests().await;
+                    return Ok(Exit::Signal);
+                }
+            };
+            let ws_stream = match connect_result {
+                Ok((ws_stream, _)) => ws_stream,
+                Err(err) => {
+                    handle_ws_error(err)?;
+                    // If OK is returned, exit
+                    return Ok(Exit::Disconnected);
+                }
+            };
+
+            // A new server has never seen the session, even after an endpoint switch
+            let resumed = reconnect && connected_url.as_ref() == Some(&endpoint.url);
+            connected_url = Some(endpoint.url.clone());
+
+            // Stream for receiving from the server, and a dedicated task writing to it
+            let (write, mut read) = ws_stream.split();
+            let (tx, mut writer) = writer::spawn(write);
+
+            // Display the reconnection message
+            if reconnect {
+                console::println!("✓ Reconnected!");
+            } else {
+                console::println!("✓ Connected to the server!");
+            }
+
+            // Advertise the client capabilities before anything else
+            handler.reset_server_capabilities();
+            let hello = ClientMessage {
+                id: None,
+                cmd: capabilities::hello(),
+            };
+            writer::send(&tx, &hello).await?;
+
+            handler.connected(reconnect);
+
+            // Client-initiated heartbeat
+            let mut heartbeat = Heartbeat::new();
+            let heartbeat_enabled = config.heartbeat.interval > 0;
+            let heartbeat_period = Duration::from_secs(config.heartbeat.interval.max(1));
+            let heartbeat_timeout = Duration::from_secs(config.heartbeat.timeout);
+            let mut heartbeat_interval =
+                time::interval_at(Instant::now() + heartbeat_period, heartbeat_period);
+            // Time the last message was received
+            let mut last_received = Instant::now();
+            // Requests that asked the client to exit
+            let (exit_tx, mut exit_rx) = mpsc::channel::<()>(1);
+
+            // Loop to process messages received from the server
+            loop {
+                let deadline = heartbeat.deadline(heartbeat_timeout);
+                let message = tokio::select! {
+                    message = read.next() => message,
+                    _ = heartbeat_interval.tick(), if heartbeat_enabled => {
+                        // Send a Ping message
+                        tx.send(Message::Ping(heartbeat.ping()))
+                            .await
+                            .context("Failed to send ping message to the server")?;
+                        continue;
+                    }
+                    _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        Err(anyhow!("Connection timed out (no response to heartbeat)"))?
+                    }
+                    _ = time::sleep_until(last_received + Duration::from_secs(READ_TIMEOUT)) => {
+                        Err(anyhow!("Connection timed out"))?
+                    }
+                    result = &mut writer => {
+                        // The writer only stops by itself when the connection is broken
+                        result.context("Failed to run the writer task")??;
+                        Err(anyhow!("Connection closed by the writer"))?
+                    }
+                    Some(()) = exit_rx.recv() => {
+                        // If the exit flag is set, exit
+                        let reason = "Exit requested by the server";
+                        handler.disconnect_guests().await;
+                        if let Err(err) = close(&tx, writer, &mut read, &handler, reason).await {
+                            console::eprintln!("☓ {}", err);
+                        }
+                        return Ok(Exit::Server);
+                    }
+                    signal = &mut shutdown => {
+                        console::println!("↪ Shutting down ({signal})...");
+                        let reason = format!("Host is shutting down ({signal})");
+                        handler.disconnect_guests().await;
+                        if let Err(err) = close(&tx, writer, &mut read, &handler, &reason).await {
+                            console::eprintln!("☓ {}", err);
+                        }
+                        return Ok(Exit::Signal);
+                    }
+                    Ok(()) = endpoints.changed() => {
+                        // The guests stay, only the server changes
+                        console::println!("↪ Endpoint changed. Switching to the new server...");
+                        let reason = "Host is switching to another server";
+                        if let Err(err) = close(&tx, writer, &mut read, &handler, reason).await {
+                            console::eprintln!("☓ {}", err);
+                        }
+                        switched = true;
+                        break;
+                    }
+                };
+                let Some(message) = message else {
+                    break;
+                };
+                last_received = Instant::now();
+
+                // Process each message
+                match message.context("Failed to receive message from the server")? {
+                    Message::Close(_) => break,
+                    Message::Ping(ping) => {
+                        // Send a Pong message
+                        tx.send(Message::Pong(ping))
+                            .await
+                            .context("Failed to send pong message to the server")?;
+
+                        // Reset the backoff
+                        backoff.reset();
+                    }
+                    Message::Pong(pong) => {
+                        // Measure the round-trip time
+                        let Some(rtt) = heartbeat.pong(&pong) else {
+                            continue;
+                        };
+                        let current = rtt.as_millis() as u64;
+                        let average = heartbeat.average().unwrap_or(rtt).as_millis() as u64;
+                        console::print_status!(
+                            console::Status::Latency,
+                            "⇄ {current}ms (avg {average}ms)"
+                        );
+
+                        // Report the latency to the server
+                        let cmd = ClientCmd::Latency { current, average };
+                        if handler.server_capabilities().accepts(&cmd) {
+                            writer::send(&tx, &ClientMessage { id: None, cmd }).await?;
+                        }
+
+                        // Reset the backoff
+                        backoff.reset();
+                    }
+                    Message::Text(text) => {
+                        // Parse the JSON data
+                        let msg: ServerMessage = serde_json::from_str(&text)
+                            .context("Failed to deserialize JSON message from the server")?;
+
+                        // The hello is handled right away so that later requests see the capabilities
+                        if let ServerCmd::Hello { .. } = msg.cmd {
+                            handler.handle_server_message(msg, &tx).await?;
+                            // Let the server resync its view of the guests, which may have changed while disconnected
+                            handler.synchronize(&tx, resumed).await?;
+                            continue;
+                        }
+
+                        // Limit the number of requests processed at the same time
+                        let Ok(permit) = in_flight.clone().try_acquire_owned() else {
+                            let res = ClientMessage {
+                                id: Some(msg.id),
+                                cmd: ClientCmd::Error {
+                                    code: ErrorStatus::Busy,
+                                },
+                            };
+                            writer::send(&tx, &res).await?;
+                            continue;
+                        };
+
+                        // Process the message in its own task so that slow requests do not block the others
+                        let handler = handler.clone();
+                        let tx = tx.clone();
+                        let exit_tx = exit_tx.clone();
+                        task::spawn(async move {
+                            let result: Result<()> = try {
+                                if handler.handle_server_message(msg, &tx).await? {
+                                    let _ = exit_tx.try_send(());
+                                }
+                            };
+                            if let Err(err) = result {
+                                let _: Result<()> = try {
+                                    console::eprintln!("☓ {}", err);
+                                };
+                            }
+                            drop(permit);
+                        });
+
+                        // Reset the backoff
+                        backoff.reset();
+                    }
+                    _ => (),
+                }
+            }
+        };
+        handler.disconnected();
+        if let Err(err) = result {
+            console::eprintln!("☓ {}", err);
+        }
+        // The latency is unknown until the next connection
+        console::print_status!(console::Status::Latency, "");
+
+        // Connect to the new endpoint right away, with its retry policy
+        if switched {
+            backoff = Backoff::new(handler.config().retry);
+            reconnect = true;
+            continue;
+        }
+
+        // Reconnect to the server if the connection is lost, with the current retry settings
+        backoff.set_policy(handler.config().retry);
+        let Some(delay) = backoff.next_delay() else {
+            console::eprintln!(
+                "☓ Connection lost. Giving up after {} reconnection attempts.",
+                backoff.attempts()
+            );
+            return Ok(Exit::Disconnected);
+        };
+        console::println!(
+            "↪ Connection lost. Reconnecting in {:.1} seconds... (Press Enter to reconnect now)",
+            delay.as_secs_f64()
+        );
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = console::wait_for_enter() => {}
+            Ok(()) = endpoints.changed() => {
+                backoff = Backoff::new(handler.config().retry);
+            }
+            signal = &mut shutdown => {
+                console::println!("↪ Shutting down ({signal})...");
+                handler.disconnect_guests().await;
+                return Ok(Exit::Signal);
+            }
+        }
+        reconnect = true;
+    }
+}
+
+/// Tells the server that the host is going offline and closes the connection
+async fn close(
+    tx: &Outbox,
+    writer: JoinHandle<Result<()>>,
+    read: &mut (impl StreamExt<Item = Result<Message, WsError>> + Unpin),
+    handler: &Handler,
+    reason: &str,
+) -> Result<()> {
+    handler.disconnected();
+
+    // Notify the server
+    let cmd = ClientCmd::Offline {
+        reason: reason.to_string(),
+    };
+    if handler.server_capabilities().accepts(&cmd) {
+        writer::send(tx, &ClientMessage { id: None, cmd }).await?;
+    }
+
+    // Send a close frame and wait briefly for the server to acknowledge it
+    let frame = CloseFrame {
+        code: CloseCode::Away,
+        reason: reason.to_string().into(),
+    };
+    tx.send(Message::Close(Some(frame)))
+        .await
+        .context("Failed to close the connection")?;
+    writer.await.context("Failed to run the writer task")??;
+    let _ = timeout(Duration::from_secs(3), async {
+        while let Some(Ok(message)) = read.next().await {
+            if let Message::Close(_) = message {
+                break;
+            }
+        }
+    })
+    .await;
+
+    Ok(())
+}
@@ -1,22 +1,362 @@
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+use schemars::{schema_for, JsonSchema};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value as Json;
 use std::{
-    env, fs,
+    collections::BTreeMap,
+    env, fmt, fs,
     path::{Path, PathBuf},
 };
+use toml::{Table, Value};
+use toml_edit::DocumentMut;
+
+use crate::proxy::Proxy;
+
+/// Name of the application directory in the user configuration directory
+const APP_NAME: &str = "remoteplay-inviter";
+
+/// Prefix of the environment variables overriding configuration values
+pub const ENV_PREFIX: &str = "REMOTEPLAY_";
 
 /// Endpoint configuration
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct EndpointConfig {
     /// Endpoint URL to connect to
     pub url: String,
+    /// Token sent to the server instead of the UUID
+    pub token: Option<String>,
+    /// Proxy URL (`http://`, `socks5://` or `socks5h://`), overriding the environment
+    ///
+    /// An empty string connects directly even if a proxy is set in the environment.
+    pub proxy: Option<String>,
+    /// TLS settings
+    #[serde(default)]
+    pub tls: TlsConfig,
+}
+
+/// TLS configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM file with certificates to trust in addition to the built-in roots
+    pub ca_file: Option<PathBuf>,
+    /// Whether to accept any server certificate (only for development servers)
+    pub insecure: bool,
+}
+
+/// Server profile, overriding the top-level settings when selected
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// Endpoint URL to connect to
+    pub url: Option<String>,
+    /// Token sent to the server instead of the UUID
+    pub token: Option<String>,
+    /// UUID of the client on this server (generated on first use)
+    pub uuid: Option<String>,
+    /// Proxy URL
+    pub proxy: Option<String>,
+    /// TLS settings
+    pub tls: Option<TlsConfig>,
+    /// Reconnection settings
+    pub retry: Option<RetryConfig>,
 }
 
-/// UUID configuration
-#[derive(Serialize, Deserialize)]
+/// Client configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct Config {
     /// UUID
     pub uuid: String,
+    /// Heartbeat settings
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    /// Reconnection settings
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Invite settings
+    #[serde(default)]
+    pub invite: InviteConfig,
+    /// Request processing settings
+    #[serde(default)]
+    pub requests: RequestConfig,
+    /// Guest and invite limits
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Who may send requests
+    #[serde(default)]
+    pub access: AccessConfig,
+    /// Which games may be shared
+    #[serde(default)]
+    pub games: GamesConfig,
+    /// Profile selected when `--profile` is not given
+    pub profile: Option<String>,
+    /// Server profiles by name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// Heartbeat configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Seconds between pings sent by the client (0 to disable)
+    pub interval: u64,
+    /// Seconds to wait for a pong before the connection is considered dead
+    pub timeout: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: 15,
+            timeout: 10,
+        }
+    }
+}
+
+/// Jitter applied to reconnection delays
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Jitter {
+    /// Exact exponential delays
+    None,
+    /// Random delay between 0 and the exponential delay
+    Full,
+    /// Random delay between the base delay and a multiple of the previous delay
+    Decorrelated,
+}
+
+/// Reconnection policy configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Delay before the first reconnection attempt in seconds (at most a day)
+    #[schemars(range(min = 0, max = 86400))]
+    pub base_delay: f64,
+    /// Maximum delay between attempts in seconds (at most a day)
+    #[schemars(range(min = 0, max = 86400))]
+    pub max_delay: f64,
+    /// Factor applied to the delay after each failed attempt
+    #[schemars(range(min = 1))]
+    pub multiplier: f64,
+    /// Jitter applied to the delay
+    pub jitter: Jitter,
+    /// Number of consecutive attempts before giving up (unlimited if not set)
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: 2.0,
+            max_delay: 120.0,
+            multiplier: 2.0,
+            jitter: Jitter::Full,
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Longest delay between attempts in seconds
+    pub const MAX_DELAY: f64 = 86400.0;
+
+    /// Checks that the delays are finite, not negative and at most a day
+    pub fn validate(&self) -> Result<()> {
+        let values = [
+            ("base_delay", self.base_delay, 0.0, Self::MAX_DELAY),
+            ("max_delay", self.max_delay, 0.0, Self::MAX_DELAY),
+            ("multiplier", self.multiplier, 1.0, f64::MAX),
+        ];
+        for (name, value, min, max) in values {
+            if !value.is_finite() || value < min {
+                bail!("Invalid retry.{name}: {value} (expected a number of at least {min})");
+            }
+            if value > max {
+                bail!("Invalid retry.{name}: {value} (expected a number of at most {max})");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Invite configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(default)]
+pub struct InviteConfig {
+    /// Seconds to wait for Steam to create an invite link
+    pub timeout: u64,
+    /// Whether to cancel the invites of a game once the host stops running it
+    pub cancel_on_game_change: bool,
+    /// Seconds before an invite that was not accepted is cancelled (0 to keep it until used)
+    pub expire_after: u64,
+}
+
+impl Default for InviteConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            cancel_on_game_change: false,
+            expire_after: 3600,
+        }
+    }
+}
+
+/// Request processing configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(default)]
+pub struct RequestConfig {
+    /// Maximum number of requests processed at the same time
+    pub max_in_flight: usize,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self { max_in_flight: 16 }
+    }
+}
+
+/// Guest and invite limits configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum number of guests connected at the same time (unlimited if not set)
+    pub max_guests: Option<usize>,
+    /// Maximum number of invites not accepted yet (unlimited if not set)
+    pub max_pending_links: Option<usize>,
+    /// Maximum number of invites per Discord user within the window (unlimited if not set)
+    pub max_links_per_user: Option<usize>,
+    /// Length of the per-user window in seconds
+    pub link_window: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_guests: None,
+            max_pending_links: None,
+            max_links_per_user: None,
+            link_window: 3600,
+        }
+    }
+}
+
+/// Access control configuration
+///
+/// Deny lists take precedence. When an allow list is not empty, only the listed IDs are accepted.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Discord user IDs allowed to send requests
+    pub allow_users: Vec<String>,
+    /// Discord user IDs never allowed to send requests
+    pub deny_users: Vec<String>,
+    /// Discord guild IDs allowed to send requests
+    pub allow_guilds: Vec<String>,
+    /// Discord guild IDs never allowed to send requests
+    pub deny_guilds: Vec<String>,
+    /// Discord channel IDs allowed to send requests
+    pub allow_channels: Vec<String>,
+    /// Discord channel IDs never allowed to send requests
+    pub deny_channels: Vec<String>,
+}
+
+impl AccessConfig {
+    /// Returns whether a request from the user, guild and channel is accepted
+    pub fn allows(&self, user: Option<&str>, guild: Option<&str>, channel: Option<&str>) -> bool {
+        let check = |allow: &[String], deny: &[String], id: Option<&str>| match id {
+            Some(id) => {
+                !deny.iter().any(|d| d == id) && (allow.is_empty() || allow.iter().any(|a| a == id))
+            }
+            // An unknown ID cannot be in an allow list
+            None => allow.is_empty(),
+        };
+        check(&self.allow_users, &self.deny_users, user)
+            && check(&self.allow_guilds, &self.deny_guilds, guild)
+            && check(&self.allow_channels, &self.deny_channels, channel)
+    }
+}
+
+/// Game sharing policy configuration
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(default)]
+pub struct GamesConfig {
+    /// App IDs that may be shared (every game if empty)
+    pub allowed: Vec<u32>,
+    /// App IDs that may never be shared
+    pub blocked: Vec<u32>,
+    /// Settings of specific games
+    pub overrides: Vec<GameConfig>,
+}
+
+impl GamesConfig {
+    /// Returns whether the game may be shared
+    pub fn is_allowed(&self, app_id: u32) -> bool {
+        !self.blocked.contains(&app_id)
+            && (self.allowed.is_empty() || self.allowed.contains(&app_id))
+    }
+
+    /// Returns the settings of the game, if any
+    pub fn get(&self, app_id: u32) -> Option<&GameConfig> {
+        self.overrides.iter().find(|game| game.app_id == app_id)
+    }
+}
+
+/// Settings of a specific game
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct GameConfig {
+    /// App ID of the game
+    pub app_id: u32,
+    /// Maximum number of guests connected at the same time while sharing this game
+    pub max_guests: Option<usize>,
+    /// Message shown on the panel of this game
+    pub welcome: Option<String>,
+}
+
+/// Every setting a configuration file may hold
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    config: Config,
+    #[serde(flatten)]
+    endpoint: EndpointConfig,
+}
+
+impl ConfigFile {
+    /// Returns the JSON Schema of a configuration file
+    pub fn schema() -> Result<Json> {
+        serde_json::to_value(schema_for!(ConfigFile)).context("Failed to serialize the schema")
+    }
+}
+
+/// Returns the alternatives of a schema, following references to the definitions
+pub fn resolve_schema<'a>(schema: &'a Json, definitions: &'a Json) -> Vec<&'a Json> {
+    if let Some(reference) = schema.get("$ref").and_then(Json::as_str) {
+        let name = reference.trim_start_matches("#/definitions/");
+        return match definitions.get(name) {
+            Some(definition) => resolve_schema(definition, definitions),
+            None => Vec::new(),
+        };
+    }
+    for combinator in ["allOf", "anyOf", "oneOf"] {
+        if let Some(schemas) = schema.get(combinator).and_then(Json::as_array) {
+            return (schemas.iter())
+                .flat_map(|s| resolve_schema(s, definitions))
+                .collect();
+        }
+    }
+    vec![schema]
+}
+
+/// Returns the types accepted by a schema, empty if any
+pub fn schema_types(schema: &Json) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Json::String(t)) => vec![t.as_str()],
+        Some(Json::Array(types)) => types.iter().filter_map(Json::as_str).collect(),
+        _ if schema.get("properties").is_some() => vec!["object"],
+        _ => Vec::new(),
+    }
 }
 
 /// Get the current executable path
@@ -38,38 +378,544 @@ pub fn get_exe_path() -> Result<PathBuf> {
     }
 }
 
-/// Read the endpoint configuration
-pub fn read_endpoint_config() -> Result<Option<EndpointConfig>> {
-    let exe_path = get_exe_path()?;
-    let config_path = exe_path.with_extension("endpoint.toml");
-
-    if config_path.exists() {
-        let config_content = fs::read_to_string(&config_path)
-            .with_context(|| format!("Unable to read endpoint config file: {:?}", &config_path))?;
-        let config: EndpointConfig =
-            toml::from_str(&config_content).context("Unable to parse endpoint config file")?;
-        Ok(Some(config))
+/// Returns the user configuration directory of the application
+///
+/// `$XDG_CONFIG_HOME/remoteplay-inviter` (`~/.config/remoteplay-inviter` if not set),
+/// or `%APPDATA%\remoteplay-inviter` on Windows.
+pub fn user_config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
     } else {
-        Ok(None)
-    }
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    base.map(|base| base.join(APP_NAME))
 }
 
-/// Read or generate the UUID configuration
-pub fn read_or_generate_config<F: Fn() -> Config>(generate_config: F) -> Result<Config> {
+/// Returns the configuration files, from the lowest to the highest precedence
+///
+/// Files next to the executable come last, so that a portable install overrides the user settings.
+pub fn config_files() -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if let Some(dir) = user_config_dir() {
+        files.push(dir.join("config.toml"));
+        files.push(dir.join("endpoint.toml"));
+    }
     let exe_path = get_exe_path()?;
-    let config_path = exe_path.with_extension("config.toml");
-
-    if config_path.exists() {
-        let config_content = fs::read_to_string(&config_path)
-            .with_context(|| format!("Unable to read UUID config file: {:?}", &config_path))?;
-        let config: Config =
-            toml::from_str(&config_content).context("Unable to parse UUID config file")?;
-        Ok(config)
+    files.push(exe_path.with_extension("config.toml"));
+    files.push(exe_path.with_extension("endpoint.toml"));
+    Ok(files)
+}
+
+/// Origin of a configuration value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Built-in default
+    Default,
+    /// Configuration file
+    File(PathBuf),
+    /// Environment variable
+    Env(String),
+    /// Command-line flag
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(name) => write!(f, "env {name}"),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Configuration merged from several layers, remembering where each value comes from
+///
+/// Later layers override earlier ones. Tables are merged key by key, any other value
+/// (including arrays) is replaced as a whole.
+#[derive(Debug, Clone, Default)]
+pub struct LayeredConfig {
+    /// Merged values
+    table: Table,
+    /// Source of every value, by `section.key` path
+    sources: BTreeMap<String, Source>,
+}
+
+impl LayeredConfig {
+    /// Merges a table of values
+    pub fn merge(&mut self, table: Table, source: &Source) {
+        merge_table(&mut self.table, table, "", source, &mut self.sources);
+    }
+
+    /// Merges a TOML file, returning whether it exists
+    pub fn merge_file(&mut self, path: &Path) -> Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read config file: {:?}", path))?;
+        let table: Table = toml::from_str(&content)
+            .with_context(|| format!("Unable to parse config file: {:?}", path))?;
+        self.merge(table, &Source::File(path.to_path_buf()));
+        Ok(true)
+    }
+
+    /// Merges the `REMOTEPLAY_*` environment variables
+    ///
+    /// `REMOTEPLAY_LIMITS_MAX_GUESTS` sets `max_guests` in the `limits` section,
+    /// and `REMOTEPLAY_URL` sets the top-level `url`.
+    pub fn merge_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            let key = match key.split_once('_') {
+                Some((section, rest))
+                    if matches!(self.table.get(section), Some(Value::Table(_))) =>
+                {
+                    format!("{section}.{rest}")
+                }
+                _ => key,
+            };
+            self.set(&key, &value, Source::Env(name.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the value at a `section.key` path
+    ///
+    /// The value is parsed as TOML (`3`, `true`, `["a", "b"]`) and taken as a string otherwise.
+    /// Values of settings holding strings are taken as strings, so that a numeric token or
+    /// Discord ID is not turned into a number.
+    pub fn set(&mut self, key: &str, value: &str, source: Source) -> Result<()> {
+        let path: Vec<&str> = key.split('.').collect();
+        if path.iter().any(|part| part.is_empty()) {
+            bail!("Invalid config key: {:?}", key);
+        }
+
+        // Schemas of the setting, none if the key is unknown
+        let schema = ConfigFile::schema()?;
+        let definitions = &schema["definitions"];
+        let mut schemas = resolve_schema(&schema, definitions);
+        for part in &path {
+            schemas = (schemas.into_iter())
+                .filter_map(|schema| {
+                    let additional = schema
+                        .get("additionalProperties")
+                        .filter(|additional| additional.is_object());
+                    schema["properties"].get(*part).or(additional)
+                })
+                .flat_map(|schema| resolve_schema(schema, definitions))
+                .collect();
+        }
+
+        let parsed = toml::from_str::<Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        let value = match parsed {
+            Some(parsed) => conform(parsed, Some(value), &schemas, definitions),
+            None => Value::String(value.to_string()),
+        };
+        self.insert(&path, value, &source);
+        Ok(())
+    }
+
+    /// Merges a single value at a path
+    fn insert(&mut self, path: &[&str], mut value: Value, source: &Source) {
+        // Nest the value in one table per section
+        for part in path[1..].iter().rev() {
+            value = Value::Table(Table::from_iter([(part.to_string(), value)]));
+        }
+        self.merge(Table::from_iter([(path[0].to_string(), value)]), source);
+    }
+
+    /// Applies the settings of a profile
+    ///
+    /// Profile settings override the defaults and the files, but not the environment variables
+    /// and the command line. A profile without a UUID gets an empty one, to be generated.
+    pub fn select_profile(&mut self, name: &str) -> Result<()> {
+        if !matches!(
+            self.table
+                .get("profiles")
+                .and_then(|profiles| profiles.get(name)),
+            Some(Value::Table(_))
+        ) {
+            bail!("Unknown profile: {}", name);
+        }
+
+        let prefix = format!("profiles.{name}.");
+        let settings: Vec<(String, Value, Source)> = self
+            .entries()
+            .filter_map(|(key, value, source)| {
+                let key = key.strip_prefix(&prefix)?;
+                Some((key.to_string(), value.clone(), source.clone()))
+            })
+            .collect();
+        let has_uuid = settings.iter().any(|(key, _, _)| key == "uuid");
+
+        let overridden = |layers: &Self, key: &str| {
+            matches!(layers.source(key), Some(Source::Env(_) | Source::Cli))
+        };
+        for (key, value, source) in settings {
+            if !overridden(self, &key) {
+                self.insert(&key.split('.').collect::<Vec<_>>(), value, &source);
+            }
+        }
+        if !has_uuid && !overridden(self, "uuid") {
+            self.insert(&["uuid"], Value::String(String::new()), &Source::Default);
+        }
+        Ok(())
+    }
+
+    /// Returns the value at a `section.key` path
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        let mut parts = key.split('.');
+        let mut value = self.table.get(parts.next()?)?;
+        for part in parts {
+            value = value.get(part)?;
+        }
+        Some(value)
+    }
+
+    /// Returns where the value at a `section.key` path comes from
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.sources.get(key)
+    }
+
+    /// Lists every value with its source, sorted by path
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Value, &Source)> {
+        self.sources
+            .iter()
+            .filter_map(|(key, source)| Some((key.as_str(), self.value(key)?, source)))
+    }
+
+    /// Deserializes the effective configuration
+    pub fn get<T: DeserializeOwned>(&self) -> Result<T> {
+        Value::Table(self.table.clone())
+            .try_into()
+            .context("Invalid configuration")
+    }
+}
+
+/// Formats a value for display, hiding the credentials
+///
+/// Tokens and UUIDs identify the host to the server and are masked wherever they are set,
+/// proxy URLs only lose their password.
+pub fn display_value(key: &str, value: &Value) -> String {
+    let name = key.rsplit('.').next().unwrap_or(key);
+    match (name, value) {
+        ("token" | "uuid", Value::String(secret)) if !secret.is_empty() => "\"***\"".to_string(),
+        ("proxy", Value::String(url)) => Proxy::parse(url)
+            .map(|proxy| format!("{:?}", proxy.to_string()))
+            .unwrap_or_else(|_| value.to_string()),
+        _ => value.to_string(),
+    }
+}
+
+/// Converts the values that the schemas expect as strings, written as `text` if given
+///
+/// Values of other types are kept, to be reported when deserializing the configuration.
+fn conform(value: Value, text: Option<&str>, schemas: &[&Json], definitions: &Json) -> Value {
+    let accepts = |kind: &str| {
+        (schemas.iter()).any(|schema| {
+            let types = schema_types(schema);
+            types.contains(&kind) || (kind == "integer" && types.contains(&"number"))
+        })
+    };
+    let children = |child: &dyn Fn(&Json) -> Option<&Json>| -> Vec<&Json> {
+        (schemas.iter())
+            .filter_map(|schema| child(schema))
+            .flat_map(|schema| resolve_schema(schema, definitions))
+            .collect()
+    };
+    match value {
+        Value::Array(items) => {
+            let schemas = children(&|schema| schema.get("items"));
+            let items = items.into_iter();
+            Value::Array(
+                items
+                    .map(|v| conform(v, None, &schemas, definitions))
+                    .collect(),
+            )
+        }
+        Value::Table(table) => Value::Table(
+            (table.into_iter())
+                .map(|(key, value)| {
+                    let schemas = children(&|schema| {
+                        let additional = schema
+                            .get("additionalProperties")
+                            .filter(|additional| additional.is_object());
+                        schema["properties"].get(&key).or(additional)
+                    });
+                    let value = conform(value, None, &schemas, definitions);
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::String(_) => value,
+        value => {
+            let kind = match value {
+                Value::Integer(_) => "integer",
+                Value::Float(_) => "number",
+                Value::Boolean(_) => "boolean",
+                _ => "datetime",
+            };
+            if !accepts(kind) && accepts("string") {
+                Value::String(text.map_or_else(|| value.to_string(), str::to_string))
+            } else {
+                value
+            }
+        }
+    }
+}
+
+/// Merges `src` into `dst`, recording the source of every replaced value
+fn merge_table(
+    dst: &mut Table,
+    src: Table,
+    prefix: &str,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in src {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match (dst.get_mut(&key), value) {
+            (Some(Value::Table(dst)), Value::Table(src)) => {
+                merge_table(dst, src, &path, source, sources)
+            }
+            (_, value) => {
+                // Forget the sources of the values being replaced
+                let nested = format!("{path}.");
+                sources.retain(|k, _| *k != path && !k.starts_with(&nested));
+                record_sources(&value, &path, source, sources);
+                dst.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Records the source of every value in a table
+fn record_sources(
+    value: &Value,
+    path: &str,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record_sources(value, &format!("{path}.{key}"), source, sources);
+            }
+        }
+        _ => {
+            sources.insert(path.to_string(), source.clone());
+        }
+    }
+}
+
+/// Loads the configuration from every layer
+///
+/// From the lowest to the highest precedence: the defaults, the configuration files,
+/// the file given on the command line, the selected profile, the `REMOTEPLAY_*` environment
+/// variables and the values set on the command line.
+pub fn load(
+    default_url: &str,
+    file: Option<&Path>,
+    overrides: &[(String, String)],
+) -> Result<LayeredConfig> {
+    let mut layers = LayeredConfig::default();
+    let endpoint = EndpointConfig {
+        url: default_url.to_string(),
+        token: None,
+        proxy: None,
+        tls: TlsConfig::default(),
+    };
+    for defaults in [
+        Table::try_from(Config::default()),
+        Table::try_from(endpoint),
+    ] {
+        layers.merge(
+            defaults.context("Unable to serialize default config")?,
+            &Source::Default,
+        );
+    }
+
+    for path in config_files()? {
+        layers.merge_file(&path)?;
+    }
+    if let Some(path) = file {
+        if !layers.merge_file(path)? {
+            bail!("Config file not found: {:?}", path);
+        }
+    }
+    layers.merge_env(env::vars())?;
+    for (key, value) in overrides {
+        layers.set(key, value, Source::Cli)?;
+    }
+    let profile = layers.value("profile").and_then(Value::as_str);
+    if let Some(profile) = profile.map(str::to_string) {
+        layers.select_profile(&profile)?;
+    }
+
+    // Values the client cannot work with, e.g. delays that are negative or not a number
+    layers.get::<Config>()?.retry.validate()?;
+    Ok(layers)
+}
+
+/// Saves the UUID so that the host keeps the same identity across restarts
+///
+/// The UUID replaces the current one in the file defining it. With a profile selected, the UUID
+/// belongs to the profile and goes to the file defining the profile. Otherwise it goes to the
+/// configuration file with the highest precedence. When there is none, a new file is created
+/// next to the executable, or in the user configuration directory if the executable directory
+/// is read-only. The new file only holds the UUID, so that it does not override the settings
+/// of the other files.
+pub fn save_uuid(layers: &mut LayeredConfig, uuid: &str) -> Result<PathBuf> {
+    save_uuid_in(layers, uuid, &config_files()?)
+}
+
+/// Saves the UUID like [`save_uuid`], with the given configuration files
+pub fn save_uuid_in(layers: &mut LayeredConfig, uuid: &str, files: &[PathBuf]) -> Result<PathBuf> {
+    // File currently defining the UUID
+    let current = match layers.source("uuid") {
+        Some(Source::File(path)) => Some(path.clone()),
+        Some(source @ (Source::Env(_) | Source::Cli)) => {
+            bail!("The UUID is set by {} and cannot be saved", source)
+        }
+        _ => None,
+    };
+
+    let profile = layers
+        .value("profile")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let key: Vec<&str> = match &profile {
+        Some(profile) => vec!["profiles", profile, "uuid"],
+        None => vec!["uuid"],
+    };
+
+    // File defining the profile
+    let defining = profile.as_ref().and_then(|profile| {
+        let prefix = format!("profiles.{profile}.");
+        layers.entries().find_map(|(key, _, source)| match source {
+            Source::File(path) if key.starts_with(&prefix) => Some(path.clone()),
+            _ => None,
+        })
+    });
+    let files: Vec<&PathBuf> = (files.iter())
+        .filter(|path| path.to_string_lossy().ends_with("config.toml"))
+        .rev()
+        .collect();
+    // Existing files first, then the ones to create
+    let candidates = (current.into_iter())
+        .chain(defining)
+        .chain(
+            files
+                .iter()
+                .filter(|path| path.exists())
+                .map(|path| path.to_path_buf()),
+        )
+        .chain(
+            files
+                .iter()
+                .filter(|path| !path.exists())
+                .map(|path| path.to_path_buf()),
+        );
+
+    let mut result = Err(anyhow::anyhow!("No location to save the UUID"));
+    for path in candidates {
+        result = write_value(&path, &key, uuid).map(|()| path);
+        if result.is_ok() {
+            break;
+        }
+    }
+    let path = result?;
+    let value = Value::String(uuid.to_string());
+    layers.insert(&key, value.clone(), &Source::File(path.clone()));
+    layers.insert(&["uuid"], value, &Source::File(path.clone()));
+    Ok(path)
+}
+
+/// Writes a string value to a configuration file, keeping the other settings and comments
+///
+/// A new file only holds the value.
+fn write_value(path: &Path, key: &[&str], value: &str) -> Result<()> {
+    let content = if path.exists() {
+        fs::read_to_string(path)
+            .with_context(|| format!("Unable to read config file: {:?}", path))?
     } else {
-        let config = generate_config();
-        let config_content = toml::to_string(&config).context("Unable to serialize config")?;
-        fs::write(&config_path, config_content)
-            .with_context(|| format!("Unable to write config file: {:?}", &config_path))?;
-        Ok(config)
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create config directory: {:?}", dir))?;
+        }
+        String::new()
+    };
+    let mut document: DocumentMut = content
+        .parse()
+        .with_context(|| format!("Unable to parse config file: {:?}", path))?;
+
+    let mut item = document.as_item_mut();
+    for part in key {
+        item = &mut item[part];
+    }
+    *item = toml_edit::value(value);
+
+    write_private(path, &document.to_string())
+        .with_context(|| format!("Unable to write config file: {:?}", path))
+}
+
+/// Writes a file atomically, through a temporary file renamed in the same directory
+///
+/// A new file is only readable by its owner, as it holds the UUID. An existing file keeps its
+/// permissions, and [`is_world_readable`] tells when they should be tightened.
+pub fn write_private(path: &Path, content: &str) -> Result<()> {
+    let name = path.file_name().context("Invalid file path")?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+
+    let result: Result<()> = try {
+        use std::io::Write;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Returns whether users other than the owner can read the file
+pub fn is_world_readable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o004 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
     }
 }
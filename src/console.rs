@@ -1,11 +1,24 @@
 use anyhow::{Context as _, Result};
 use crossterm::{cursor, terminal, QueueableCommand};
-use std::io::{stdout, Write};
-use std::sync::{LazyLock, Mutex};
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::{LazyLock, Mutex, Once};
+use tokio::sync::Notify;
 
 /// Last line
 static LAST_LINE: LazyLock<Mutex<String>> = LazyLock::new(|| Mutex::new("".to_string()));
 
+/// Status line sections, in display order
+#[derive(Clone, Copy)]
+pub enum Status {
+    /// Connected players
+    Players,
+    /// Connection latency
+    Latency,
+}
+
+/// Text of each status line section
+static STATUS: Mutex<[String; 2]> = Mutex::new([String::new(), String::new()]);
+
 /// Clears the current line
 pub fn clear_line() -> Result<()> {
     stdout()
@@ -24,6 +37,21 @@ pub fn save_line(args: std::fmt::Arguments<'_>) -> Result<()> {
     Ok(())
 }
 
+/// Saves a section of the status line and rebuilds the last line from all sections
+pub fn save_status(status: Status, args: std::fmt::Arguments<'_>) -> Result<()> {
+    let mut sections = STATUS
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Failed to lock status line"))?;
+    sections[status as usize] = std::fmt::format(args);
+    let line = sections
+        .iter()
+        .filter(|section| !section.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join("  ");
+    save_line(format_args!("{line}"))
+}
+
 /// Updates the current line
 /// <https://stackoverflow.com/a/59890400>
 pub fn update_line() -> Result<()> {
@@ -44,41 +72,67 @@ pub fn update_line() -> Result<()> {
     Ok(())
 }
 
+/// Notified whenever Enter is pressed
+static ENTER: Notify = Notify::const_new();
+
+/// Waits until Enter is pressed on the console
+pub async fn wait_for_enter() {
+    // Read the console on a dedicated thread, only once
+    static STDIN_READER: Once = Once::new();
+    STDIN_READER.call_once(|| {
+        std::thread::spawn(|| {
+            for _ in stdin().lock().lines().map_while(Result::ok) {
+                ENTER.notify_waiters();
+            }
+        });
+    });
+
+    ENTER.notified().await
+}
+
 /// println macro
-macro_rules! println {
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __console_println {
     ($($arg:tt)*) => {{
         $crate::console::clear_line()?;
         std::println!($($arg)*); // Call the original macro
         $crate::console::update_line()?;
     }};
 }
-pub(crate) use println;
+pub use crate::__console_println as println;
 
 /// eprintln macro
-macro_rules! eprintln {
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __console_eprintln {
     ($($arg:tt)*) => {{
         $crate::console::clear_line()?;
         std::eprintln!($($arg)*); // Call the original macro
         $crate::console::update_line()?;
     }};
 }
-pub(crate) use eprintln;
+pub use crate::__console_eprintln as eprintln;
 
 /// printdoc macro
-macro_rules! printdoc {
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __console_printdoc {
     ($($arg:tt)*) => {{
         $crate::console::clear_line()?;
         indoc::printdoc!($($arg)*); // Call the original macro
         $crate::console::update_line()?;
     }};
 }
-pub(crate) use printdoc;
+pub use crate::__console_printdoc as printdoc;
 
-/// print_update macro
-macro_rules! print_update {
-    ($($arg:tt)*) => {{
-        $crate::console::save_line(format_args!($($arg)*))?;
+/// print_status macro
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __console_print_status {
+    ($status:expr, $($arg:tt)*) => {{
+        $crate::console::save_status($status, format_args!($($arg)*))?;
         $crate::console::update_line()?;
     }};
 }
-pub(crate) use print_update;
+pub use crate::__console_print_status as print_status;
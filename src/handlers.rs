@@ -1,65 +1,255 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clipboard::{ClipboardContext, ClipboardProvider};
-use futures::SinkExt;
 use std::{
-    collections::{BTreeSet, HashMap},
-    sync::Arc,
-    time::Duration,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use steam_stuff::{GameID, GameUID};
+use steam_stuff::{GameID, GameUID, RemotePlayBackend};
 use tokio::{
-    sync::{
-        mpsc::{channel, Receiver, Sender},
-        Mutex,
-    },
+    sync::{oneshot, Mutex},
     task,
-    time::interval,
+    time::{interval, timeout, Instant},
 };
-use tokio_tungstenite::tungstenite::{protocol::Message, Error as WsError};
 
-use crate::SteamStuff;
 use crate::{
+    capabilities::{self, ServerCapabilities},
+    config::Config,
     console,
-    models::{ClientCmd, ClientMessage, ErrorStatus, ServerCmd, ServerMessage},
+    models::{
+        ClientCmd, ClientMessage, ErrorStatus, GuestInfo, PendingInvite, PlayerInfo, ServerCmd,
+        ServerMessage, User,
+    },
+    writer::{self, Outbox},
+    VERSION,
 };
 
 pub struct GuestData {
-    pub guest_map: HashMap<u64, String>,
-    pub user_set: BTreeSet<u64>,
+    /// Discord user that claimed each guest ID
+    pub guest_map: HashMap<u64, User>,
+    /// Guest IDs of invites that have not been accepted yet
+    pub pending_set: BTreeSet<u64>,
+    /// Steam IDs of the friends invited directly by guest ID
+    pub direct_invites: HashMap<u64, u64>,
+    /// App IDs of the invites that have not been accepted yet by guest ID
+    pub invite_games: HashMap<u64, u32>,
+    /// Time each invite that has not been accepted yet was created by guest ID
+    pub invite_times: HashMap<u64, Instant>,
+    /// Steam IDs of the connected guests by guest ID
+    pub user_map: BTreeMap<u64, u64>,
+    /// Time each connected guest joined by guest ID
+    pub join_times: HashMap<u64, SystemTime>,
+    /// Times of the recent invites by Discord user ID
+    pub link_history: HashMap<String, VecDeque<Instant>>,
+    /// Invites allowed by the limits that are not pending yet
+    pub reserved: usize,
+}
+
+impl GuestData {
+    /// Returns the description of a guest sent to the server
+    pub fn guest_info(&self, guest_id: u64, steam_id: u64) -> GuestInfo {
+        GuestInfo {
+            guest_id,
+            steam_id: steam_id.to_string(),
+            user: self.guest_map.get(&guest_id).cloned(),
+        }
+    }
+
+    /// Returns the connected guests and the invites that have not been accepted yet
+    pub fn snapshot(&self) -> (Vec<GuestInfo>, Vec<PendingInvite>) {
+        let guests = self
+            .user_map
+            .iter()
+            .map(|(&guest_id, &steam_id)| self.guest_info(guest_id, steam_id))
+            .collect();
+        let pending = self
+            .pending_set
+            .iter()
+            .map(|&guest_id| PendingInvite {
+                guest_id,
+                steam_id: self.direct_invites.get(&guest_id).map(|id| id.to_string()),
+                user: self.guest_map.get(&guest_id).cloned(),
+            })
+            .collect();
+        (guests, pending)
+    }
+
+    /// Forgets an invite that has not been accepted yet, returning the Steam ID it was sent to
+    /// (0 for an invite link)
+    pub fn remove_invite(&mut self, guest_id: u64) -> u64 {
+        self.pending_set.remove(&guest_id);
+        self.invite_games.remove(&guest_id);
+        self.invite_times.remove(&guest_id);
+        self.direct_invites.remove(&guest_id).unwrap_or(0)
+    }
 }
 
+/// Senders waiting for the invite URL of each guest ID
+type InviteWaiters = HashMap<u64, oneshot::Sender<String>>;
+
 pub struct Handler {
-    steam: Arc<Mutex<SteamStuff>>,
-    invite_tx: Sender<(u64, String)>,
-    invite_rx: Receiver<(u64, String)>,
+    steam: Arc<Mutex<dyn RemotePlayBackend>>,
+    /// Current configuration, replaced when the files change
+    config: Arc<StdMutex<Arc<Config>>>,
+    invite_waiters: Arc<StdMutex<InviteWaiters>>,
     guest_data: Arc<Mutex<GuestData>>,
+    capabilities: Arc<StdMutex<ServerCapabilities>>,
+    /// Sender of the current connection, for unsolicited events
+    outbox: Arc<StdMutex<Option<Outbox>>>,
+    /// Time the handler was created
+    started_at: Instant,
+    /// Number of connections and reconnections
+    connections: StdMutex<(u64, u64)>,
 }
 
 impl Handler {
-    pub fn new(steam: Arc<Mutex<SteamStuff>>) -> Self {
-        let (invite_tx, invite_rx) = channel::<(u64, String)>(32);
+    pub fn new(steam: Arc<Mutex<dyn RemotePlayBackend>>, config: Config) -> Self {
         Self {
             steam,
-            invite_tx,
-            invite_rx,
+            config: Arc::new(StdMutex::new(Arc::new(config))),
+            invite_waiters: Arc::new(StdMutex::new(InviteWaiters::new())),
             guest_data: Arc::new(Mutex::new(GuestData {
-                guest_map: HashMap::<u64, String>::new(),
-                user_set: BTreeSet::<u64>::new(),
+                guest_map: HashMap::<u64, User>::new(),
+                pending_set: BTreeSet::<u64>::new(),
+                direct_invites: HashMap::<u64, u64>::new(),
+                invite_games: HashMap::<u64, u32>::new(),
+                invite_times: HashMap::<u64, Instant>::new(),
+                user_map: BTreeMap::<u64, u64>::new(),
+                join_times: HashMap::<u64, SystemTime>::new(),
+                link_history: HashMap::<String, VecDeque<Instant>>::new(),
+                reserved: 0,
             })),
+            capabilities: Arc::new(StdMutex::new(ServerCapabilities::default())),
+            outbox: Arc::new(StdMutex::new(None)),
+            started_at: Instant::now(),
+            connections: StdMutex::new((0, 0)),
+        }
+    }
+
+    /// Returns the current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.lock().unwrap().clone()
+    }
+
+    /// Replaces the configuration, used by the requests processed from now on
+    pub fn reload_config(&self, config: Config) {
+        *self.config.lock().unwrap() = Arc::new(config);
+    }
+
+    /// Returns the capabilities advertised by the server of the current connection
+    pub fn server_capabilities(&self) -> ServerCapabilities {
+        self.capabilities.lock().unwrap().clone()
+    }
+
+    /// Forgets the capabilities of the previous connection until the server answers the hello
+    pub fn reset_server_capabilities(&self) {
+        *self.capabilities.lock().unwrap() = ServerCapabilities::default();
+    }
+
+    /// Counts a new connection to the server
+    ///
+    /// Every connection after the first one counts as a reconnect, whatever the endpoint.
+    pub fn connected(&self, reconnect: bool) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.0 += 1;
+        if reconnect {
+            connections.1 += 1;
         }
     }
 
+    /// Sends the state snapshot if the server enabled it, and routes the events to the connection
+    ///
+    /// Called once the server answered the hello. Both happen under the guest lock so that no
+    /// event is lost or sent before the snapshot.
+    pub async fn synchronize(&self, tx: &Outbox, resumed: bool) -> Result<()> {
+        let guest_data = self.guest_data.lock().await;
+        let (guests, pending) = guest_data.snapshot();
+        let sync = ClientCmd::Sync {
+            resumed,
+            guests,
+            pending,
+        };
+        if self.server_capabilities().accepts(&sync) {
+            let sync = ClientMessage {
+                id: None,
+                cmd: sync,
+            };
+            writer::send(tx, &sync).await?;
+        }
+        *self.outbox.lock().unwrap() = Some(tx.clone());
+        Ok(())
+    }
+
+    /// Stops sending events to the connection, which would otherwise be kept open
+    pub fn disconnected(&self) {
+        *self.outbox.lock().unwrap() = None;
+    }
+
     /**
      * Handles server messages
      * @return Whether to exit (true: exit)
      */
-    pub async fn handle_server_message(
-        &mut self,
-        msg: ServerMessage,
-        write: &mut (impl SinkExt<Message, Error = WsError> + Unpin),
-    ) -> Result<bool> {
+    pub async fn handle_server_message(&self, msg: ServerMessage, tx: &Outbox) -> Result<bool> {
+        let config = self.config();
+
+        // Reject requests from users the host does not accept
+        let user_request = matches!(
+            msg.cmd,
+            ServerCmd::GameId
+                | ServerCmd::Link { .. }
+                | ServerCmd::Kick { .. }
+                | ServerCmd::Players
+        );
+        let user_id = msg.user.as_ref().map(|u| u.id.as_str());
+        if user_request
+            && !config
+                .access
+                .allows(user_id, msg.guild.as_deref(), msg.channel.as_deref())
+        {
+            let user_name = msg.user.as_ref().map_or_else(|| "?", |u| &u.name);
+            console::eprintln!(
+                "☓ Request Forbidden   : user={user_name}, user_id={}, guild={}, channel={}",
+                user_id.unwrap_or("?"),
+                msg.guild.as_deref().unwrap_or("?"),
+                msg.channel.as_deref().unwrap_or("?"),
+            );
+            let res = ClientMessage {
+                id: Some(msg.id),
+                cmd: ClientCmd::Error {
+                    code: ErrorStatus::Forbidden,
+                },
+            };
+            writer::send(tx, &res).await?;
+            return Ok(false);
+        }
+
         // Branch based on command type
         let res = match msg.cmd {
+            ServerCmd::Hello {
+                protocol,
+                commands,
+                features,
+            } => {
+                // Remember what the server supports for this connection
+                let capabilities = ServerCapabilities {
+                    protocol,
+                    commands: commands.into_iter().collect(),
+                    features: features.into_iter().collect(),
+                };
+                console::println!(
+                    "✓ Server capabilities: protocol={}, features=[{}]",
+                    capabilities.protocol,
+                    capabilities
+                        .features
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                *self.capabilities.lock().unwrap() = capabilities;
+
+                return Ok(false);
+            }
             ServerCmd::Message { text: data, copy } => {
                 // Indent the message
                 let message = data
@@ -94,7 +284,7 @@ impl Handler {
                     // If the game is not running
                     // Create the response data
                     break 'cmd ClientMessage {
-                        id: msg.id,
+                        id: Some(msg.id),
                         cmd: ClientCmd::Error {
                             code: ErrorStatus::InvalidApp,
                         },
@@ -104,11 +294,22 @@ impl Handler {
                 let app_id = game_id.app_id;
                 let game_uid: GameUID = game_id.into();
 
+                if !config.games.is_allowed(app_id) {
+                    // If the host does not share this game
+                    console::eprintln!("☓ Game Not Shared     : game_id={app_id}");
+                    break 'cmd ClientMessage {
+                        id: Some(msg.id),
+                        cmd: ClientCmd::Error {
+                            code: ErrorStatus::BlockedApp,
+                        },
+                    };
+                }
+
                 if !self.steam.lock().await.can_remote_play_together(game_uid) {
                     // If the game is not supported for Remote Play Together
                     // Create the response data
                     break 'cmd ClientMessage {
-                        id: msg.id,
+                        id: Some(msg.id),
                         cmd: ClientCmd::Error {
                             code: ErrorStatus::UnsupportedApp,
                         },
@@ -124,38 +325,289 @@ impl Handler {
 
                 // Create the response data
                 ClientMessage {
-                    id: msg.id,
-                    cmd: ClientCmd::GameId { game: app_id },
+                    id: Some(msg.id),
+                    cmd: ClientCmd::GameId {
+                        game: app_id,
+                        welcome: config
+                            .games
+                            .get(app_id)
+                            .and_then(|game| game.welcome.clone()),
+                    },
                 }
             }
-            ServerCmd::Link { game } => {
+            ServerCmd::Link { game, steam_id } => 'cmd: {
                 // Get the game ID
                 let game_uid: GameUID = GameID::new(game, 0, 0).into();
+                let claimer = msg.user.as_ref().map_or_else(|| "?", |s| &s.name);
 
-                // Create an invite link
-                let recv = self.invite_rx.recv();
-                self.steam.lock().await.send_invite(0, game_uid);
-                let (guest_id, connect_url) = recv.await.unwrap();
+                // Steam ID of the friend to invite directly (0: anyone with the link)
+                let invitee = match steam_id.as_deref().map(str::parse::<u64>) {
+                    None => 0,
+                    Some(Ok(invitee)) if invitee != 0 => invitee,
+                    Some(_) => {
+                        break 'cmd ClientMessage {
+                            id: Some(msg.id),
+                            cmd: ClientCmd::Error {
+                                code: ErrorStatus::InvalidCmd,
+                            },
+                        };
+                    }
+                };
+                let direct = invitee != 0;
+
+                if !config.games.is_allowed(game) {
+                    // If the host does not share this game
+                    console::eprintln!("☓ Game Not Shared     : claimer={claimer}, game_id={game}");
+                    break 'cmd ClientMessage {
+                        id: Some(msg.id),
+                        cmd: ClientCmd::Error {
+                            code: ErrorStatus::BlockedApp,
+                        },
+                    };
+                }
+
+                // Enforce the limits set by the host
+                let reserved_at = match self.check_limits(msg.user.as_ref(), game).await {
+                    Ok(reserved_at) => reserved_at,
+                    Err(code) => {
+                        console::eprintln!("☓ Invite Refused      : claimer={claimer}, game_id={game}, reason={code:?}");
+                        break 'cmd ClientMessage {
+                            id: Some(msg.id),
+                            cmd: ClientCmd::Error { code },
+                        };
+                    }
+                };
+
+                // Create an invite link, or send the invite to the friend
+                // The waiter is registered before the callbacks can run again, as they need the lock
+                let (invite_tx, invite_rx) = oneshot::channel();
+                let guest_id = {
+                    let steam = self.steam.lock().await;
+                    let guest_id = steam.send_invite(invitee, game_uid);
+                    if guest_id != 0 {
+                        self.invite_waiters
+                            .lock()
+                            .unwrap()
+                            .insert(guest_id, invite_tx);
+                    }
+                    guest_id
+                };
+                if guest_id == 0 {
+                    // If Steam refused to create the invite
+                    self.release(msg.user.as_ref(), reserved_at).await;
+                    console::eprintln!("☓ Invite Failed       : claimer={claimer}, game_id={game}");
+                    break 'cmd ClientMessage {
+                        id: Some(msg.id),
+                        cmd: ClientCmd::Error {
+                            code: ErrorStatus::InviteFailed,
+                        },
+                    };
+                }
+
+                // Wait for the invite URL of this guest
+                let invite_timeout = Duration::from_secs(config.invite.timeout);
+                let connect_url = match timeout(invite_timeout, invite_rx).await {
+                    Ok(Ok(connect_url)) => connect_url,
+                    _ => {
+                        // Give up on the invite so that it cannot be used later
+                        self.invite_waiters.lock().unwrap().remove(&guest_id);
+                        self.steam.lock().await.cancel_invite(invitee, guest_id);
+                        self.release(msg.user.as_ref(), reserved_at).await;
+                        console::eprintln!(
+                            "☓ Invite Timed Out    : claimer={claimer}, guest_id={guest_id}, game_id={game}",
+                        );
+                        break 'cmd ClientMessage {
+                            id: Some(msg.id),
+                            cmd: ClientCmd::Error {
+                                code: ErrorStatus::InviteTimeout,
+                            },
+                        };
+                    }
+                };
 
                 // Associate the Discord user with guest_id
+                let mut guest_data = self.guest_data.lock().await;
+                guest_data.reserved -= 1;
+                guest_data.pending_set.insert(guest_id);
+                guest_data.invite_games.insert(guest_id, game);
+                guest_data.invite_times.insert(guest_id, Instant::now());
+                if direct {
+                    guest_data.direct_invites.insert(guest_id, invitee);
+                }
                 if let Some(user) = &msg.user {
-                    self.guest_data
-                        .lock()
-                        .await
-                        .guest_map
-                        .insert(guest_id, user.name.clone());
+                    guest_data.guest_map.insert(guest_id, user.clone());
                 }
+                drop(guest_data);
 
                 // Log the output
-                let claimer = msg.user.as_ref().map_or_else(|| "?", |s| &s.name);
-                console::println!(
-                    "-> Create Invite Link : claimer={claimer}, guest_id={guest_id}, game_id={game}, invite_url={connect_url}", 
-                );
+                if direct {
+                    console::println!(
+                        "-> Send Direct Invite : claimer={claimer}, guest_id={guest_id}, game_id={game}, steam_id={invitee}",
+                    );
+                } else {
+                    console::println!(
+                        "-> Create Invite Link : claimer={claimer}, guest_id={guest_id}, game_id={game}, invite_url={connect_url}", 
+                    );
+                }
+
+                // Create the response data
+                ClientMessage {
+                    id: Some(msg.id),
+                    cmd: ClientCmd::Link {
+                        url: connect_url,
+                        direct,
+                    },
+                }
+            }
+            ServerCmd::Kick { guest, user_id } => 'cmd: {
+                if guest.is_none() && user_id.is_none() {
+                    // Nothing to kick
+                    break 'cmd ClientMessage {
+                        id: Some(msg.id),
+                        cmd: ClientCmd::Error {
+                            code: ErrorStatus::InvalidCmd,
+                        },
+                    };
+                }
+
+                // Find the guests by guest ID or claiming Discord user
+                let mut guest_data = self.guest_data.lock().await;
+                let matches = |guest_id: u64| {
+                    guest == Some(guest_id)
+                        || (user_id.is_some()
+                            && guest_data.guest_map.get(&guest_id).map(|u| &u.id)
+                                == user_id.as_ref())
+                };
+                let active = guest_data
+                    .user_map
+                    .iter()
+                    .filter(|(&guest_id, _)| matches(guest_id))
+                    .map(|(&guest_id, &invitee)| (guest_id, invitee))
+                    .collect::<Vec<_>>();
+                let pending = guest_data
+                    .pending_set
+                    .iter()
+                    .copied()
+                    .filter(|&guest_id| matches(guest_id))
+                    .collect::<Vec<_>>();
+                if active.is_empty() && pending.is_empty() {
+                    break 'cmd ClientMessage {
+                        id: Some(msg.id),
+                        cmd: ClientCmd::Error {
+                            code: ErrorStatus::UnknownGuest,
+                        },
+                    };
+                }
+
+                // End the sessions and cancel the invites
+                let steam = self.steam.lock().await;
+                for &(guest_id, invitee) in &active {
+                    steam.cancel_invite(invitee, guest_id);
+                    guest_data.user_map.remove(&guest_id);
+                    guest_data.join_times.remove(&guest_id);
+                }
+                for &guest_id in &pending {
+                    let invitee = guest_data.remove_invite(guest_id);
+                    steam.cancel_invite(invitee, guest_id);
+                }
+                drop(steam);
+
+                // Log the output
+                let requester = msg.user.as_ref().map_or_else(|| "?", |s| &s.name);
+                for &(guest_id, invitee) in &active {
+                    let user_name = guest_data
+                        .guest_map
+                        .get(&guest_id)
+                        .map_or_else(|| "?", |u| &u.name);
+                    console::println!(
+                        "-> Kick Player        : requester={requester}, claimer={user_name}, guest_id={guest_id}, steam_id={invitee}",
+                    );
+                }
+                for &guest_id in &pending {
+                    let user_name = guest_data
+                        .guest_map
+                        .get(&guest_id)
+                        .map_or_else(|| "?", |u| &u.name);
+                    console::println!(
+                        "-> Cancel Invite Link : requester={requester}, claimer={user_name}, guest_id={guest_id}",
+                    );
+                }
+
+                // Create the response data
+                let mut guests = active
+                    .iter()
+                    .map(|&(guest_id, _)| guest_id)
+                    .chain(pending)
+                    .collect::<Vec<_>>();
+                guests.sort_unstable();
+                ClientMessage {
+                    id: Some(msg.id),
+                    cmd: ClientCmd::Kick { guests },
+                }
+            }
+            ServerCmd::Players => {
+                // Build the roster of the connected guests
+                let guest_data = self.guest_data.lock().await;
+                let now = SystemTime::now();
+                let players = guest_data
+                    .user_map
+                    .iter()
+                    .map(|(&guest_id, &steam_id)| {
+                        let joined_at =
+                            guest_data.join_times.get(&guest_id).copied().unwrap_or(now);
+                        PlayerInfo {
+                            guest_id,
+                            steam_id: steam_id.to_string(),
+                            user: guest_data.guest_map.get(&guest_id).cloned(),
+                            joined_at: joined_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_secs(),
+                            duration: now.duration_since(joined_at).unwrap_or_default().as_secs(),
+                        }
+                    })
+                    .collect();
 
                 // Create the response data
                 ClientMessage {
-                    id: msg.id,
-                    cmd: ClientCmd::Link { url: connect_url },
+                    id: Some(msg.id),
+                    cmd: ClientCmd::Players { players },
+                }
+            }
+            ServerCmd::Status => {
+                // Describe the running game
+                let (game, remote_play_together) = {
+                    let steam = self.steam.lock().await;
+                    let game_id = steam.get_running_game_id();
+                    if game_id.is_valid_app() {
+                        (
+                            Some(game_id.app_id),
+                            steam.can_remote_play_together(game_id.into()),
+                        )
+                    } else {
+                        (None, false)
+                    }
+                };
+                let (guests, pending) = {
+                    let guest_data = self.guest_data.lock().await;
+                    (guest_data.user_map.len(), guest_data.pending_set.len())
+                };
+                let (connections, reconnects) = *self.connections.lock().unwrap();
+
+                // Create the response data
+                ClientMessage {
+                    id: Some(msg.id),
+                    cmd: ClientCmd::Status {
+                        version: VERSION.to_string(),
+                        os: capabilities::os(),
+                        uptime: self.started_at.elapsed().as_secs(),
+                        connections,
+                        reconnects,
+                        game,
+                        remote_play_together,
+                        guests,
+                        pending,
+                    },
                 }
             }
             ServerCmd::Exit => {
@@ -165,7 +617,7 @@ impl Handler {
             ServerCmd::Invalid => {
                 // Create the response data
                 ClientMessage {
-                    id: msg.id,
+                    id: Some(msg.id),
                     cmd: ClientCmd::Error {
                         code: ErrorStatus::InvalidCmd,
                     },
@@ -173,29 +625,91 @@ impl Handler {
             }
         };
 
-        // Convert the response data to JSON
-        let res_str = serde_json::to_string(&res)
-            .context("Failed to serialize JSON message for the server")?;
         // Send the response data
-        write
-            .send(Message::Text(res_str))
-            .await
-            .context("Failed to send message to the server")?;
+        writer::send(tx, &res).await?;
 
         Ok(false)
     }
 
+    /// Checks the guest and invite limits, reserving a slot for the invite if allowed
+    ///
+    /// The invite also counts towards the user quota, at the returned time. The slot is released
+    /// by [`Self::release`] if the invite fails, or once the invite is pending.
+    async fn check_limits(
+        &self,
+        user: Option<&User>,
+        game: u32,
+    ) -> Result<Option<Instant>, ErrorStatus> {
+        let config = self.config();
+        let limits = &config.limits;
+        let mut guest_data = self.guest_data.lock().await;
+
+        // Invites waiting for Steam count as outstanding too
+        let pending = guest_data.pending_set.len() + guest_data.reserved;
+
+        // Capacity of the host, which may be lower for this game, including the invited guests
+        let game_max_guests = config.games.get(game).and_then(|game| game.max_guests);
+        let max_guests = limits.max_guests.into_iter().chain(game_max_guests).min();
+        if max_guests.is_some_and(|max| guest_data.user_map.len() + pending >= max) {
+            return Err(ErrorStatus::Full);
+        }
+        if limits.max_pending_links.is_some_and(|max| pending >= max) {
+            return Err(ErrorStatus::Full);
+        }
+
+        // Quota of the Discord user within the window
+        let mut reserved_at = None;
+        if let (Some(max), Some(user)) = (limits.max_links_per_user, user) {
+            let window = Duration::from_secs(limits.link_window);
+            let history = guest_data.link_history.entry(user.id.clone()).or_default();
+            while history.front().is_some_and(|time| time.elapsed() >= window) {
+                history.pop_front();
+            }
+            if history.len() >= max {
+                return Err(ErrorStatus::QuotaExceeded);
+            }
+            let now = Instant::now();
+            history.push_back(now);
+            reserved_at = Some(now);
+        }
+
+        guest_data.reserved += 1;
+        Ok(reserved_at)
+    }
+
+    /// Releases the slot reserved for an invite that failed, and refunds the user quota
+    async fn release(&self, user: Option<&User>, reserved_at: Option<Instant>) {
+        let mut guest_data = self.guest_data.lock().await;
+        guest_data.reserved -= 1;
+        if let (Some(user), Some(reserved_at)) = (user, reserved_at) {
+            if let Some(history) = guest_data.link_history.get_mut(&user.id) {
+                if let Some(index) = history.iter().position(|time| *time == reserved_at) {
+                    history.remove(index);
+                }
+            }
+        }
+    }
+
     // Set up SteamStuff callbacks
     pub async fn setup_steam_callbacks(&self) {
         // Register callbacks
         let steam = self.steam.lock().await;
         let guest_data = self.guest_data.clone();
-        steam.set_on_remote_started(move |invitee, guest_id| {
+        let events = (self.outbox.clone(), self.capabilities.clone());
+        steam.set_on_remote_started(Box::new(move |invitee, guest_id| {
             let guest_data = guest_data.clone();
+            let (outbox, capabilities) = events.clone();
             tokio::spawn(async move {
                 let mut guest_data = guest_data.lock().await;
-                guest_data.user_set.insert(guest_id);
-                let user_name = guest_data.guest_map.get(&guest_id).map_or_else(|| "?", |s| s);
+                guest_data.remove_invite(guest_id);
+                guest_data.user_map.insert(guest_id, invitee);
+                guest_data.join_times.insert(guest_id, SystemTime::now());
+
+                // Notify the server
+                let guest = guest_data.guest_info(guest_id, invitee);
+                send_event(&outbox, &capabilities, ClientCmd::PlayerJoined { guest }).await;
+
+                let user_name = guest_data.guest_map.get(&guest_id).map_or_else(|| "?", |u| &u.name);
                 let _: Result<()> = try {
                     // Log the output
                     console::println!(
@@ -204,22 +718,30 @@ impl Handler {
 
                     // Display the user list
                     let users_text = guest_data
-                        .user_set
-                        .iter()
-                        .map(|id| format!("[{}]{}", id, guest_data.guest_map.get(&id).map_or_else(|| "?", |s| s)))
+                        .user_map
+                        .keys()
+                        .map(|id| format!("[{}]{}", id, guest_data.guest_map.get(id).map_or_else(|| "?", |u| &u.name)))
                         .collect::<Vec<String>>()
                         .join(", ");
-                    console::print_update!("★ Players({}): {users_text}", guest_data.user_set.len());
+                    console::print_status!(console::Status::Players, "★ Players({}): {users_text}", guest_data.user_map.len());
                 };
             });
-        });
+        }));
         let guest_data = self.guest_data.clone();
-        steam.set_on_remote_stopped(move |invitee, guest_id| {
+        let events = (self.outbox.clone(), self.capabilities.clone());
+        steam.set_on_remote_stopped(Box::new(move |invitee, guest_id| {
             let guest_data = guest_data.clone();
+            let (outbox, capabilities) = events.clone();
             tokio::spawn(async move {
                 let mut guest_data = guest_data.lock().await;
-                guest_data.user_set.remove(&guest_id);
-                let user_name = guest_data.guest_map.get(&guest_id).map_or_else(|| "?", |s| s);
+                guest_data.user_map.remove(&guest_id);
+                guest_data.join_times.remove(&guest_id);
+
+                // Notify the server
+                let guest = guest_data.guest_info(guest_id, invitee);
+                send_event(&outbox, &capabilities, ClientCmd::PlayerLeft { guest }).await;
+
+                let user_name = guest_data.guest_map.get(&guest_id).map_or_else(|| "?", |u| &u.name);
                 let _: Result<()> = try {
                     // Log the output
                     console::println!(
@@ -228,35 +750,174 @@ impl Handler {
 
                     // Display the user list
                     let users_text = guest_data
-                        .user_set
-                        .iter()
-                        .map(|id| format!("[{}]{}", id, guest_data.guest_map.get(&id).map_or_else(|| "?", |s| s)))
+                        .user_map
+                        .keys()
+                        .map(|id| format!("[{}]{}", id, guest_data.guest_map.get(id).map_or_else(|| "?", |u| &u.name)))
                         .collect::<Vec<String>>()
                         .join(", ");
-                    console::print_update!("★ Players({}): {users_text}", guest_data.user_set.len());
+                    console::print_status!(console::Status::Players, "★ Players({}): {users_text}", guest_data.user_map.len());
                 };
             });
-        });
-        let invite_tx = self.invite_tx.clone();
-        steam.set_on_remote_invited(move |_invitee, guest_id, connect_url| {
-            // Send the invite link
-            let invite_tx = invite_tx.clone();
-            let connect_url = String::from(connect_url);
-            tokio::spawn(async move {
-                invite_tx.send((guest_id, connect_url)).await.unwrap();
-            });
-        });
+        }));
+        let invite_waiters = self.invite_waiters.clone();
+        steam.set_on_remote_invited(Box::new(move |_invitee, guest_id, connect_url: &str| {
+            // Send the invite link to the request waiting for this guest
+            if let Some(invite_tx) = invite_waiters.lock().unwrap().remove(&guest_id) {
+                let _ = invite_tx.send(String::from(connect_url));
+            }
+        }));
+    }
+
+    /// Cancels every pending invite and disconnects every connected guest
+    pub async fn disconnect_guests(&self) {
+        let mut guest_data = self.guest_data.lock().await;
+        let steam = self.steam.lock().await;
+
+        // Cancel the invites that have not been accepted yet
+        let pending = std::mem::take(&mut guest_data.pending_set);
+        let direct_invites = std::mem::take(&mut guest_data.direct_invites);
+        guest_data.invite_games.clear();
+        guest_data.invite_times.clear();
+        for &guest_id in &pending {
+            steam.cancel_invite(
+                direct_invites.get(&guest_id).copied().unwrap_or(0),
+                guest_id,
+            );
+        }
+        // End the sessions of the connected guests
+        let users = std::mem::take(&mut guest_data.user_map);
+        guest_data.join_times.clear();
+        for (&guest_id, &invitee) in &users {
+            steam.cancel_invite(invitee, guest_id);
+        }
+
+        let _: Result<()> = try {
+            // Log the output
+            for (&guest_id, &invitee) in &users {
+                let user_name = guest_data
+                    .guest_map
+                    .get(&guest_id)
+                    .map_or_else(|| "?", |u| &u.name);
+                console::println!(
+                    "-> Player Disconnected  : claimer={user_name}, guest_id={guest_id}, steam_id={invitee}",
+                );
+            }
+            if !pending.is_empty() {
+                console::println!("-> Cancel Invite Links  : count={}", pending.len());
+            }
+            console::print_status!(console::Status::Players, "");
+        };
     }
 
     // Start a task to periodically call SteamStuff_RunCallbacks
+    // The running game is watched at the same time
     pub fn run_steam_callbacks(&self) {
         let steam_clone = self.steam.clone();
+        let guest_data = self.guest_data.clone();
+        let (outbox, capabilities) = (self.outbox.clone(), self.capabilities.clone());
+        let config = self.config.clone();
         task::spawn(async move {
             let mut interval = interval(Duration::from_millis(200));
+            let mut running_game = None;
             loop {
                 interval.tick().await;
-                steam_clone.lock().await.run_callbacks();
+                let game = {
+                    let steam = steam_clone.lock().await;
+                    steam.run_callbacks();
+                    let game_id = steam.get_running_game_id();
+                    game_id.is_valid_app().then_some(game_id.app_id)
+                };
+
+                // Invites never used are cancelled, so that they stop counting towards the limits
+                let expire_after = config.lock().unwrap().invite.expire_after;
+                if expire_after > 0 {
+                    let mut guest_data = guest_data.lock().await;
+                    let expire_after = Duration::from_secs(expire_after);
+                    let expired = (guest_data.invite_times.iter())
+                        .filter(|(_, time)| time.elapsed() >= expire_after)
+                        .map(|(&guest_id, _)| guest_id)
+                        .collect::<Vec<_>>();
+                    if !expired.is_empty() {
+                        let steam = steam_clone.lock().await;
+                        for &guest_id in &expired {
+                            let invitee = guest_data.remove_invite(guest_id);
+                            steam.cancel_invite(invitee, guest_id);
+                        }
+                        drop(steam);
+                        let _: Result<()> = try {
+                            console::println!("-> Invite Links Expired : count={}", expired.len());
+                        };
+                    }
+                }
+
+                if game == running_game {
+                    continue;
+                }
+                let previous = std::mem::replace(&mut running_game, game);
+
+                let _: Result<()> = try {
+                    // Log the output
+                    let show = |game: Option<u32>| {
+                        game.map_or_else(|| "-".to_string(), |id| id.to_string())
+                    };
+                    console::println!(
+                        "-> Game Changed       : game_id={}, previous={}",
+                        show(game),
+                        show(previous)
+                    );
+                };
+
+                // Invites for the game the host no longer runs cannot be used
+                let cancel_on_game_change = config.lock().unwrap().invite.cancel_on_game_change;
+                if let (true, Some(previous)) = (cancel_on_game_change, previous) {
+                    let mut guest_data = guest_data.lock().await;
+                    let stale = guest_data
+                        .pending_set
+                        .iter()
+                        .copied()
+                        .filter(|guest_id| guest_data.invite_games.get(guest_id) == Some(&previous))
+                        .collect::<Vec<_>>();
+                    let steam = steam_clone.lock().await;
+                    for &guest_id in &stale {
+                        let invitee = guest_data.remove_invite(guest_id);
+                        steam.cancel_invite(invitee, guest_id);
+                    }
+                    drop(steam);
+                    if !stale.is_empty() {
+                        let _: Result<()> = try {
+                            console::println!(
+                                "-> Cancel Invite Links  : count={}, game_id={previous}",
+                                stale.len()
+                            );
+                        };
+                    }
+                }
+
+                // Notify the server
+                send_event(
+                    &outbox,
+                    &capabilities,
+                    ClientCmd::GameChanged { game, previous },
+                )
+                .await;
             }
         });
     }
 }
+
+/// Sends an event to the server of the current connection, if the server enabled events
+async fn send_event(
+    outbox: &StdMutex<Option<Outbox>>,
+    capabilities: &StdMutex<ServerCapabilities>,
+    cmd: ClientCmd,
+) {
+    if !capabilities.lock().unwrap().accepts(&cmd) {
+        return;
+    }
+    let Some(tx) = outbox.lock().unwrap().clone() else {
+        return;
+    };
+    let event = ClientMessage { id: None, cmd };
+    // Events missed while disconnected are covered by the sync of the next connection
+    let _ = writer::send(&tx, &event).await;
+}
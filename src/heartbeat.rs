@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/// Number of samples used for the average latency
+const SAMPLES: usize = 10;
+
+/// Client-initiated heartbeat state for a single connection
+pub struct Heartbeat {
+    /// Sequence number of the last ping sent
+    seq: u64,
+    /// Ping waiting for a pong (sequence number, sent at)
+    pending: Option<(u64, Instant)>,
+    /// Recent round-trip times
+    samples: VecDeque<Duration>,
+}
+
+impl Heartbeat {
+    /// Creates a heartbeat with no measurements
+    pub fn new() -> Self {
+        Self {
+            seq: 0,
+            pending: None,
+            samples: VecDeque::with_capacity(SAMPLES),
+        }
+    }
+
+    /// Returns the payload of the next ping and starts measuring
+    pub fn ping(&mut self) -> Vec<u8> {
+        self.seq += 1;
+        // Keep the oldest unanswered ping so that a stalled connection is still detected
+        if self.pending.is_none() {
+            self.pending = Some((self.seq, Instant::now()));
+        }
+        self.seq.to_be_bytes().to_vec()
+    }
+
+    /// Records a pong and returns the measured round-trip time
+    pub fn pong(&mut self, payload: &[u8]) -> Option<Duration> {
+        let seq = u64::from_be_bytes(payload.try_into().ok()?);
+        let (_, sent_at) = self.pending.filter(|(pending, _)| seq >= *pending)?;
+        self.pending = None;
+
+        let rtt = sent_at.elapsed();
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        Some(rtt)
+    }
+
+    /// Returns when the unanswered ping times out
+    pub fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.pending.map(|(_, sent_at)| sent_at + timeout)
+    }
+
+    /// Returns the average of the recent round-trip times
+    pub fn average(&self) -> Option<Duration> {
+        let count = self.samples.len() as u32;
+        (count > 0).then(|| self.samples.iter().sum::<Duration>() / count)
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{bail, Context, Result};
+use std::{fs, path::Path};
+use uuid::Uuid;
+
+use crate::{
+    args::IdentityCommand,
+    config::{self, LayeredConfig, Source},
+    console,
+};
+
+/// Runs an identity subcommand
+pub fn run(command: &IdentityCommand, layers: &mut LayeredConfig) -> Result<()> {
+    let uuid = layers.get::<config::Config>()?.uuid;
+    match command {
+        IdentityCommand::Show => {
+            if uuid.is_empty() {
+                console::println!("✓ No UUID yet. It is generated on the first run.");
+                return Ok(());
+            }
+            console::println!("✓ UUID: {}", uuid);
+            if let Some(source) = layers.source("uuid") {
+                console::println!("-> Stored in: {}", source);
+            }
+            warn_if_readable(layers)?;
+        }
+        IdentityCommand::Regenerate => {
+            let uuid = Uuid::new_v4().to_string();
+            let path = config::save_uuid(layers, &uuid)?;
+            console::println!("✓ Generated a new UUID: {}", uuid);
+            console::println!("-> Saved to: {}", path.display());
+            console::println!("-> The previous UUID no longer identifies this host.");
+        }
+        IdentityCommand::Export(path) => {
+            if uuid.is_empty() {
+                bail!("No UUID to export yet. It is generated on the first run.");
+            }
+            let content = format!(
+                "# Remote Play Inviter identity. Keep this file private.\nuuid = {}\n",
+                toml::Value::String(uuid)
+            );
+            config::write_private(path, &content)
+                .with_context(|| format!("Unable to write identity file: {:?}", path))?;
+            console::println!("✓ Exported the UUID to: {}", path.display());
+        }
+        IdentityCommand::Import(path) => {
+            let uuid = read_identity(path)?;
+            let saved = config::save_uuid(layers, &uuid)?;
+            console::println!("✓ Imported the UUID: {}", uuid);
+            console::println!("-> Saved to: {}", saved.display());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the UUID of an identity file, either exported or holding only the UUID
+pub fn read_identity(path: &Path) -> Result<String> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read identity file: {:?}", path))?;
+    let uuid = match toml::from_str::<toml::Table>(&content) {
+        Ok(table) => match table.get("uuid").and_then(toml::Value::as_str) {
+            Some(uuid) => uuid.to_string(),
+            None => bail!("No UUID in identity file: {:?}", path),
+        },
+        Err(_) => content.trim().to_string(),
+    };
+    let uuid = Uuid::parse_str(&uuid)
+        .with_context(|| format!("Invalid UUID in identity file: {:?}", path))?;
+    Ok(uuid.to_string())
+}
+
+/// Warns when other users can read the file holding the UUID
+pub fn warn_if_readable(layers: &LayeredConfig) -> Result<()> {
+    if let Some(Source::File(path)) = layers.source("uuid") {
+        if config::is_world_readable(path) {
+            console::eprintln!(
+                "⚠ {} is readable by other users, who could use its UUID. Run: chmod 600 {:?}",
+                path.display(),
+                path
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+#![feature(try_blocks)]
+
+pub mod args;
+pub mod capabilities;
+pub mod client;
+pub mod config;
+pub mod console;
+pub mod handlers;
+mod heartbeat;
+pub mod identity;
+pub mod models;
+pub mod proxy;
+pub mod reload;
+mod retry;
+pub mod schema;
+pub mod shutdown;
+pub mod tls;
+pub mod validate;
+pub mod writer;
+mod ws_error_handler;
+
+// Version
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -2,44 +2,36 @@
 
 use anyhow::{Context as _, Result};
 use dotenvy_macro::dotenv;
-use futures::SinkExt;
-use futures_util::stream::StreamExt;
-use std::sync::Arc;
-use steam_stuff::SteamStuff;
-use tokio::{
-    sync::Mutex,
-    time::{self, timeout, Duration},
-};
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{
-        http::{uri::Builder, Uri},
-        protocol::Message,
-    },
+use remoteplay_inviter::{
+    args::{Args, Command, ConfigCommand},
+    client::{self, Endpoint, Exit},
+    config::{self, Config, EndpointConfig, LayeredConfig, Source},
+    console,
+    handlers::Handler,
+    identity,
+    reload::Reloader,
+    schema, shutdown, validate, VERSION,
 };
+use std::sync::Arc;
+use steam_stuff::{RemotePlayBackend, SteamStuff};
+use tokio::sync::{watch, Mutex};
 use uuid::Uuid;
 
-mod config;
-mod console;
-mod handlers;
-mod models;
-mod retry;
-mod ws_error_handler;
-
-use config::{read_or_generate_config, Config};
-use handlers::Handler;
-use models::*;
-use retry::RetrySec;
-use ws_error_handler::handle_ws_error;
-
-// Version
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 // Endpoint URL
 const DEFAULT_URL: &str = dotenv!("ENDPOINT_URL");
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Command-line arguments (errors are reported after the banner)
+    let args = Args::parse(std::env::args().skip(1));
+
+    // Schema command (before the banner, and without the console escape codes, so that the
+    // output can be redirected as is)
+    if matches!(&args, Ok(args) if args.schema) {
+        std::println!("{}", schema::export()?);
+        return Ok(());
+    }
+
     // Event loop
     'main: {
         console::printdoc! {"
@@ -54,30 +46,105 @@ async fn main() -> Result<()> {
         
         "};
 
+        let args = match args {
+            Ok(args) => args,
+            Err(err) => {
+                console::eprintln!("☓ {}", err);
+                break 'main;
+            }
+        };
+
         // Version command
-        if std::env::args().any(|arg| arg == "--version" || arg == "-v") {
+        if args.version {
             console::println!("✓ Version: {}", VERSION);
             return Ok(());
         }
 
         // Help command
-        if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        if args.help {
             let program = std::env::current_exe()
                 .ok()
                 .and_then(|f| f.file_name().map(|f| f.to_string_lossy().into_owned()))
                 .unwrap_or_else(|| "remoteplay-inviter".to_owned());
             console::printdoc! {"
-                Usage: {program} [options]
+                Usage: {program} [options] [command]
+
+                Commands:
+                    identity [show]            Show the UUID identifying this host
+                    identity regenerate        Replace the UUID with a new one
+                    identity export <file>     Write the UUID to a file
+                    identity import <file>     Use the UUID of an exported file
+                    config check               Validate the configuration files (exit status 1 if invalid)
 
                 Options:
-                    -v, --version    Display the version of the program
-                    -h, --help       Display this help message
+                    -v, --version              Display the version of the program
+                    -h, --help                 Display this help message
+                        --schema               Print the JSON Schema of the messages exchanged with the server
+                        --print-config         Print the effective configuration and where each value comes from
+                        --config <file>        Read an additional configuration file
+                        --url <url>            Endpoint URL to connect to
+                        --proxy <url>          Proxy URL (empty to connect directly)
+                        --profile <name>       Use the server profile defined in [profiles.<name>]
+                        --set <key>=<value>    Set a configuration value (e.g. limits.max_guests=4)
+
+                Configuration files are read from the user configuration directory, then next to the
+                executable. REMOTEPLAY_<SECTION>_<KEY> environment variables and the options above
+                take precedence over the files.
             "};
             return Ok(());
         }
 
+        // Config check command (before loading, so that every problem is reported)
+        if let Some(Command::Config(ConfigCommand::Check)) = &args.command {
+            let result: Result<usize> = try {
+                let files: Vec<_> = (config::config_files()?.into_iter())
+                    .chain(args.config.clone())
+                    .collect();
+                let load = || config::load(DEFAULT_URL, args.config.as_deref(), &args.overrides);
+                validate::run(&files, load)?
+            };
+            match result {
+                Ok(0) => return Ok(()),
+                Ok(_) => std::process::exit(1),
+                Err(err) => {
+                    console::eprintln!("☓ {:#}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+
+        // Configuration merged from the files, the environment and the command line
+        let mut layers = match config::load(DEFAULT_URL, args.config.as_deref(), &args.overrides) {
+            Ok(layers) => layers,
+            Err(err) => {
+                console::eprintln!("☓ {:#}", err);
+                console::eprintln!(
+                    "-> Run with `config check` to find every problem in the files."
+                );
+                break 'main;
+            }
+        };
+
+        // Print config command
+        if args.print_config {
+            return print_config(&layers);
+        }
+
+        // Subcommands
+        if let Some(command) = &args.command {
+            let result = match command {
+                Command::Identity(command) => identity::run(command, &mut layers),
+                Command::Config(ConfigCommand::Check) => unreachable!("checked before loading"),
+            };
+            if let Err(err) = result {
+                console::eprintln!("☓ {:#}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+
         // Initialize SteamStuff
-        let steam = match SteamStuff::new()
+        let steam: Arc<Mutex<dyn RemotePlayBackend>> = match SteamStuff::new()
             .context("Failed to connect to Steam Client. Please make sure Steam is running.")
         {
             Ok(steam) => Arc::new(Mutex::new(steam)),
@@ -87,135 +154,84 @@ async fn main() -> Result<()> {
             }
         };
 
-        // Create a Handler
-        let mut handler = Handler::new(steam.clone());
-
-        // Set up Steam callbacks
-        handler.setup_steam_callbacks().await;
-        // Start a task to periodically call Steam callbacks
-        handler.run_steam_callbacks();
+        // Endpoint to connect to, the client configuration and the session ID
+        let result: Result<(Endpoint, Config, u32)> = try {
+            let endpoint: EndpointConfig = layers.get()?;
+            let mut config: Config = layers.get()?;
 
-        // Reconnection flag
-        let mut reconnect = false;
-        // Retry seconds
-        let mut retry_sec = RetrySec::new();
-
-        // URL to connect to
-        let result: Result<String> = try {
-            // Read the endpoint configuration file
-            let endpoint_config = config::read_endpoint_config()?;
+            // Generate the UUID on the first run
+            if config.uuid.is_empty() {
+                config.uuid = Uuid::new_v4().to_string();
+                let path = config::save_uuid(&mut layers, &config.uuid)?;
+                console::println!("✓ Saved a new UUID to: {}", path.display());
+            }
+            identity::warn_if_readable(&layers)?;
 
-            // Read or generate the configuration file (if it doesn't exist)
-            let config = read_or_generate_config(|| Config {
-                uuid: Uuid::new_v4().to_string(),
-            })?;
+            // Selected profile
+            if let Some(profile) = &config.profile {
+                console::println!("✓ Using profile: {}", profile);
+            }
 
             // Session ID
             let session_id: u32 = rand::random();
 
             // Endpoint URL
-            let endpoint_url = match endpoint_config {
-                Some(e) => {
-                    console::println!("✓ Using custom endpoint URL: {}", e.url);
-                    e.url
-                }
-                None => DEFAULT_URL.to_string(),
-            };
+            if layers.source("url") != Some(&Source::Default) {
+                console::println!("✓ Using custom endpoint URL: {}", endpoint.url);
+            }
+            if endpoint.tls.insecure {
+                console::println!("✓ Server certificate verification is disabled");
+            }
 
-            // Create the URL
-            let uri: Uri = endpoint_url.parse().context("Failed to parse URL")?;
-            let uri = Builder::from(uri)
-                .path_and_query(format!(
-                    "/ws?v={VERSION}&token={0}&session={session_id}",
-                    config.uuid
-                ))
-                .build()
-                .context("Failed to build URL")?;
-            uri.to_string()
+            // URL, proxy from the configuration or the environment, and TLS settings
+            let client_endpoint = Endpoint::new(&endpoint, &config.uuid, session_id)?;
+            if let Some(proxy) = &client_endpoint.proxy {
+                console::println!("✓ Using proxy: {}", proxy);
+            }
+            (client_endpoint, config, session_id)
         };
-        let url = match result {
-            Ok(url) => url,
+        let (endpoint, config, session_id) = match result {
+            Ok(result) => result,
             Err(err) => {
                 console::eprintln!("☓ {}", err);
                 break 'main;
             }
         };
 
-        loop {
-            let result: Result<()> = try {
-                // Display the reconnection message
-                if reconnect {
-                    console::println!("↪ Reconnecting to the server...");
-                }
+        // Create a Handler
+        let handler = Arc::new(Handler::new(steam.clone(), config));
 
-                // Create a WebSocket client
-                let connect_result = timeout(Duration::from_secs(10), connect_async(&url))
-                    .await
-                    .context("Connection timed out to the server")?;
-                let ws_stream = match connect_result {
-                    Ok((ws_stream, _)) => ws_stream,
-                    Err(err) => {
-                        handle_ws_error(err)?;
-                        // If OK is returned, break the loop and exit
-                        break 'main;
-                    }
-                };
-
-                // Stream and sink for communicating with the server
-                let (mut write, mut read) = ws_stream.split();
-
-                // Display the reconnection message
-                if reconnect {
-                    console::println!("✓ Reconnected!");
-                } else {
-                    console::println!("✓ Connected to the server!");
-                }
+        // Set up Steam callbacks
+        handler.setup_steam_callbacks().await;
+        // Start a task to periodically call Steam callbacks
+        handler.run_steam_callbacks();
 
-                // Loop to process messages received from the server
-                while let Some(message) = timeout(Duration::from_secs(60), read.next())
-                    .await
-                    .context("Connection timed out")?
-                {
-                    // Process each message
-                    match message.context("Failed to receive message from the server")? {
-                        Message::Close(_) => break,
-                        Message::Ping(ping) => {
-                            // Send a Pong message
-                            write
-                                .send(Message::Pong(ping))
-                                .await
-                                .context("Failed to send pong message to the server")?;
-
-                            // Reset the retry seconds
-                            retry_sec.reset();
-                        }
-                        Message::Text(text) => {
-                            // Parse the JSON data
-                            let msg: ServerMessage = serde_json::from_str(&text)
-                                .context("Failed to deserialize JSON message from the server")?;
-
-                            // Process the message
-                            if handler.handle_server_message(msg, &mut write).await? {
-                                // If the exit flag is set, break the loop and exit
-                                break 'main;
-                            }
-
-                            // Reset the retry seconds
-                            retry_sec.reset();
-                        }
-                        _ => (),
-                    }
-                }
-            };
-            if let Err(err) = result {
+        // Apply the changes to the configuration files while running
+        let (endpoint_tx, endpoints) = watch::channel(endpoint);
+        let files = match config::config_files() {
+            Ok(files) => files.into_iter().chain(args.config.clone()).collect(),
+            Err(err) => {
                 console::eprintln!("☓ {}", err);
+                break 'main;
             }
-
-            // Reconnect to the server if the connection is lost
-            let sec = retry_sec.next();
-            console::println!("↪ Connection lost. Reconnecting in {sec} seconds...");
-            time::sleep(Duration::from_secs(sec)).await;
-            reconnect = true;
+        };
+        let load = move || config::load(DEFAULT_URL, args.config.as_deref(), &args.overrides);
+        Reloader::new(
+            load,
+            files,
+            layers,
+            session_id,
+            handler.clone(),
+            endpoint_tx,
+        )
+        .spawn();
+
+        // Connect to the server and process messages until exit
+        match client::run(endpoints, handler, shutdown::signal()).await {
+            // Exit immediately when shut down by a signal
+            Ok(Exit::Signal) => return Ok(()),
+            Ok(_) => (),
+            Err(err) => console::eprintln!("☓ {}", err),
         }
     }
 
@@ -225,3 +241,13 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Prints every configuration value with its source
+fn print_config(layers: &LayeredConfig) -> Result<()> {
+    console::println!("✓ Effective configuration:");
+    for (key, value, source) in layers.entries() {
+        let value = config::display_value(key, value);
+        console::println!("-> {:<30}: {}  ({})", key, value, source);
+    }
+    Ok(())
+}
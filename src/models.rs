@@ -1,7 +1,8 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Connection error message
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ConnectionErrorMessage {
     /// Error message
     pub message: Option<String>,
@@ -10,7 +11,7 @@ pub struct ConnectionErrorMessage {
 }
 
 /// Error types for the daemon server
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "error")]
 pub enum ConnectionErrorType {
     /// Outdated daemon
@@ -26,21 +27,40 @@ pub enum ConnectionErrorType {
 }
 
 /// A data structure to represent a request to the daemon
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ServerMessage {
-    /// Request ID
+    /// Request ID (empty for messages not expecting a response)
+    #[serde(default)]
     pub id: String,
     /// Request user
     pub user: Option<User>,
+    /// Discord guild ID the request comes from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guild: Option<String>,
+    /// Discord channel ID the request comes from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
     /// Request type
     #[serde(flatten)]
     pub cmd: ServerCmd,
 }
 
 /// Request Type
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "cmd")]
 pub enum ServerCmd {
+    /// Answer to the client hello
+    #[serde(rename = "hello")]
+    Hello {
+        /// Protocol version of the server
+        protocol: u32,
+        /// Client commands understood by the server
+        #[serde(default)]
+        commands: Vec<String>,
+        /// Optional features enabled by the server
+        #[serde(default)]
+        features: Vec<String>,
+    },
     /// Announce message
     #[serde(rename = "message")]
     Message {
@@ -57,7 +77,24 @@ pub enum ServerCmd {
     Link {
         /// Game ID
         game: u32,
+        /// Steam ID of a friend to invite directly instead of creating a link
+        #[serde(default)]
+        steam_id: Option<String>,
+    },
+    /// Kick a guest by guest ID, or every guest claimed by a Discord user
+    #[serde(rename = "kick")]
+    Kick {
+        /// Guest ID
+        guest: Option<u64>,
+        /// Discord user ID of the claimer
+        user_id: Option<String>,
     },
+    /// List the connected guests
+    #[serde(rename = "players")]
+    Players,
+    /// Describe the state of the host
+    #[serde(rename = "status")]
+    Status,
     /// Exit request
     #[serde(rename = "exit")]
     Exit,
@@ -66,30 +103,87 @@ pub enum ServerCmd {
 }
 
 /// A data structure to represent a response from the daemon
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ClientMessage {
-    /// Request ID
-    pub id: String,
+    /// Request ID (none for messages not answering a request)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     /// Request type
     #[serde(flatten)]
     pub cmd: ClientCmd,
 }
 
 /// Request Type
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "cmd")]
 pub enum ClientCmd {
+    /// First message of every connection, advertising the client capabilities
+    #[serde(rename = "hello")]
+    Hello {
+        /// Protocol version of the client
+        protocol: u32,
+        /// Client version
+        version: String,
+        /// Server commands understood by the client
+        commands: Vec<String>,
+        /// Operating system and architecture
+        os: String,
+        /// User interface locale
+        locale: String,
+        /// Optional features supported by the client
+        features: Vec<String>,
+    },
     /// Generate a game id
     #[serde(rename = "game")]
     GameId {
         /// Game ID
         game: u32,
+        /// Message set by the host for this game
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        welcome: Option<String>,
     },
     /// Generate a link request
     #[serde(rename = "link")]
     Link {
         /// Invite URL
         url: String,
+        /// Whether the invite was sent directly to the Steam friend
+        #[serde(default)]
+        direct: bool,
+    },
+    /// Kick response
+    #[serde(rename = "kick")]
+    Kick {
+        /// Guest IDs that were kicked
+        guests: Vec<u64>,
+    },
+    /// Player list response
+    #[serde(rename = "players")]
+    Players {
+        /// Connected guests in order of guest ID
+        players: Vec<PlayerInfo>,
+    },
+    /// Status response
+    #[serde(rename = "status")]
+    Status {
+        /// Client version
+        version: String,
+        /// Operating system and architecture
+        os: String,
+        /// Seconds since the client started
+        uptime: u64,
+        /// Number of successful connections to the server
+        connections: u64,
+        /// Number of those connections that were reconnections
+        reconnects: u64,
+        /// App ID of the running game, if any
+        game: Option<u32>,
+        /// Whether the running game supports Remote Play Together
+        remote_play_together: bool,
+        /// Number of connected guests
+        guests: usize,
+        /// Number of invites not accepted yet
+        pending: usize,
     },
     /// Error response
     #[serde(rename = "error")]
@@ -97,17 +191,100 @@ pub enum ClientCmd {
         /// Error code
         code: ErrorStatus,
     },
+    /// The host is going offline
+    #[serde(rename = "offline")]
+    Offline {
+        /// Reason for going offline
+        reason: String,
+    },
+    /// State of the host sent after the hello, so that the server can resync after a reconnect
+    #[serde(rename = "sync")]
+    Sync {
+        /// Whether the connection resumes the session of a previous connection
+        resumed: bool,
+        /// Connected guests
+        guests: Vec<GuestInfo>,
+        /// Invites that have not been accepted yet
+        pending: Vec<PendingInvite>,
+    },
+    /// A guest joined the session
+    #[serde(rename = "player_joined")]
+    PlayerJoined {
+        /// Guest that joined
+        #[serde(flatten)]
+        guest: GuestInfo,
+    },
+    /// A guest left the session
+    #[serde(rename = "player_left")]
+    PlayerLeft {
+        /// Guest that left
+        #[serde(flatten)]
+        guest: GuestInfo,
+    },
+    /// The host started, switched or quit a game
+    #[serde(rename = "game_changed")]
+    GameChanged {
+        /// App ID of the game now running, if any
+        game: Option<u32>,
+        /// App ID of the game running before, if any
+        previous: Option<u32>,
+    },
+    /// Connection latency measured by the client heartbeat
+    #[serde(rename = "latency")]
+    Latency {
+        /// Latest round-trip time in milliseconds
+        current: u64,
+        /// Average round-trip time in milliseconds
+        average: u64,
+    },
 }
 
 /// User information
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct User {
     pub id: String,
     pub name: String,
 }
 
+/// Guest connected to the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GuestInfo {
+    /// Guest ID
+    pub guest_id: u64,
+    /// Steam ID, as a string since it does not fit in a JavaScript number
+    pub steam_id: String,
+    /// Discord user that claimed the invite
+    pub user: Option<User>,
+}
+
+/// Guest in the player list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PlayerInfo {
+    /// Guest ID
+    pub guest_id: u64,
+    /// Steam ID, as a string since it does not fit in a JavaScript number
+    pub steam_id: String,
+    /// Discord user that claimed the invite
+    pub user: Option<User>,
+    /// Time the guest joined, in seconds since the Unix epoch
+    pub joined_at: u64,
+    /// Time since the guest joined, in seconds
+    pub duration: u64,
+}
+
+/// Invite that has not been accepted yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PendingInvite {
+    /// Guest ID
+    pub guest_id: u64,
+    /// Steam ID of the friend invited directly, if any
+    pub steam_id: Option<String>,
+    /// Discord user that claimed the invite
+    pub user: Option<User>,
+}
+
 /// Error statuses
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ErrorStatus {
     /// The command is invalid
@@ -116,4 +293,20 @@ pub enum ErrorStatus {
     InvalidApp,
     /// The app does not support remote play
     UnsupportedApp,
+    /// The host does not share this app
+    BlockedApp,
+    /// Steam refused to create the invite
+    InviteFailed,
+    /// Steam did not create the invite in time
+    InviteTimeout,
+    /// Too many requests are being processed
+    Busy,
+    /// No guest matches the request
+    UnknownGuest,
+    /// The host cannot take more guests or invites
+    Full,
+    /// The Discord user created too many invites recently
+    QuotaExceeded,
+    /// The host does not accept requests from this user, guild or channel
+    Forbidden,
 }
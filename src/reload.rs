@@ -0,0 +1,153 @@
+use anyhow::Result;
+use std::{collections::BTreeSet, fs, path::PathBuf, sync::Arc};
+use tokio::{
+    sync::watch,
+    task::{self, JoinHandle},
+    time::{interval, Duration, MissedTickBehavior},
+};
+use uuid::Uuid;
+
+use crate::{
+    client::Endpoint,
+    config::{self, Config, EndpointConfig, LayeredConfig},
+    console,
+    handlers::Handler,
+};
+
+/// Interval between checks of the configuration files
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings that need a new connection when they change
+const ENDPOINT_KEYS: &[&str] = &["url", "token", "proxy", "tls", "uuid"];
+
+/// Watches the configuration files and applies their changes while running
+///
+/// Policy settings (limits, access lists, games, invites) apply to the next requests.
+/// Heartbeat and request settings apply from the next connection, retry settings from the next
+/// reconnection attempt, and a change of the endpoint or of the token reconnects to the server.
+pub struct Reloader<F> {
+    /// Loads the configuration from every layer
+    load: F,
+    /// Files to watch
+    files: Vec<PathBuf>,
+    /// Contents of the files when last loaded
+    contents: Vec<Option<String>>,
+    /// Configuration currently applied
+    layers: LayeredConfig,
+    /// Session ID of the process, kept across endpoints
+    session_id: u32,
+    handler: Arc<Handler>,
+    endpoint: watch::Sender<Endpoint>,
+}
+
+impl<F: Fn() -> Result<LayeredConfig> + Send + 'static> Reloader<F> {
+    pub fn new(
+        load: F,
+        files: Vec<PathBuf>,
+        layers: LayeredConfig,
+        session_id: u32,
+        handler: Arc<Handler>,
+        endpoint: watch::Sender<Endpoint>,
+    ) -> Self {
+        let contents = read_all(&files);
+        Self {
+            load,
+            files,
+            contents,
+            layers,
+            session_id,
+            handler,
+            endpoint,
+        }
+    }
+
+    /// Checks the files periodically in a task
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        task::spawn(async move {
+            let mut interval = interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                self.check();
+            }
+        })
+    }
+
+    /// Reloads the configuration if a file changed, returning the changed settings
+    ///
+    /// An invalid configuration is reported once and the current one is kept.
+    pub fn check(&mut self) -> Vec<String> {
+        let contents = read_all(&self.files);
+        if contents == self.contents {
+            return Vec::new();
+        }
+        self.contents = contents;
+
+        match self.reload() {
+            Ok(changed) => changed,
+            Err(err) => {
+                let _: Result<()> = try {
+                    console::eprintln!("☓ Unable to reload the configuration: {:#}", err);
+                };
+                Vec::new()
+            }
+        }
+    }
+
+    /// Loads the configuration and applies the changed settings
+    fn reload(&mut self) -> Result<Vec<String>> {
+        let mut layers = (self.load)()?;
+        let mut config: Config = layers.get()?;
+        let endpoint: EndpointConfig = layers.get()?;
+
+        // A newly selected profile may not have a UUID yet
+        if config.uuid.is_empty() {
+            config.uuid = Uuid::new_v4().to_string();
+            let path = config::save_uuid(&mut layers, &config.uuid)?;
+            console::println!("✓ Saved a new UUID to: {}", path.display());
+        }
+
+        let changed = changed_keys(&self.layers, &layers);
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+
+        // Build the new endpoint before applying anything, so that an invalid one changes nothing
+        let reconnect = changed.iter().any(|key| {
+            let section = key.split('.').next().unwrap_or(key);
+            ENDPOINT_KEYS.contains(&section)
+        });
+        let endpoint = if reconnect {
+            Some(Endpoint::new(&endpoint, &config.uuid, self.session_id)?)
+        } else {
+            None
+        };
+
+        console::println!("↪ Reloaded configuration: {}", changed.join(", "));
+        self.handler.reload_config(config);
+        self.layers = layers;
+        if let Some(endpoint) = endpoint {
+            self.endpoint.send_replace(endpoint);
+        }
+        Ok(changed)
+    }
+}
+
+/// Reads the files, `None` for the missing ones
+fn read_all(files: &[PathBuf]) -> Vec<Option<String>> {
+    files
+        .iter()
+        .map(|path| fs::read_to_string(path).ok())
+        .collect()
+}
+
+/// Returns the settings whose value differs, sorted
+fn changed_keys(old: &LayeredConfig, new: &LayeredConfig) -> Vec<String> {
+    let keys: BTreeSet<&str> = (old.entries().map(|(key, _, _)| key))
+        .chain(new.entries().map(|(key, _, _)| key))
+        .collect();
+    keys.into_iter()
+        .filter(|key| old.value(key) != new.value(key))
+        .map(str::to_string)
+        .collect()
+}
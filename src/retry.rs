@@ -1,20 +1,87 @@
-/// Retry seconds
-pub struct RetrySec(u64);
+use crate::config::{Jitter, RetryConfig};
+use rand::Rng;
+use std::time::Duration;
 
-impl RetrySec {
-    /// Creates a new RetrySec with an initial value of 1 second
-    pub fn new() -> Self {
-        Self(1)
+/// Reconnection backoff following the configured retry policy
+pub struct Backoff {
+    /// Retry policy
+    policy: RetryConfig,
+    /// Number of attempts since the last reset
+    attempts: u32,
+    /// Previous delay in seconds (for decorrelated jitter)
+    prev: f64,
+}
+
+impl Backoff {
+    /// Creates a new Backoff for the policy
+    pub fn new(policy: RetryConfig) -> Self {
+        let prev = policy.base_delay;
+        Self {
+            policy,
+            attempts: 0,
+            prev,
+        }
+    }
+
+    /// Returns the delay before the next attempt, or None if the attempt limit is reached
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        let RetryConfig {
+            base_delay,
+            max_delay,
+            multiplier,
+            jitter,
+            max_attempts,
+        } = self.policy;
+
+        if max_attempts.is_some_and(|max| self.attempts >= max) {
+            return None;
+        }
+        // Invalid values are rejected when loading the configuration, keep the delays finite anyway
+        let finite = |value: f64, default: f64| if value.is_finite() { value } else { default };
+        let base_delay = finite(base_delay, 0.0).max(0.0);
+        let max_delay = finite(max_delay, 0.0).max(0.0);
+        let multiplier = finite(multiplier, 1.0);
+
+        // Exponential delay, capped at the maximum delay
+        let delay = (base_delay * multiplier.powi(self.attempts as i32))
+            .min(max_delay)
+            .max(0.0);
+        let delay = match jitter {
+            Jitter::None => delay,
+            // Random delay between 0 and the exponential delay
+            Jitter::Full => rand::thread_rng().gen_range(0.0..=delay),
+            // Random delay between the base delay and a multiple of the previous delay
+            Jitter::Decorrelated => {
+                let upper = (self.prev * multiplier).min(max_delay).max(base_delay);
+                rand::thread_rng()
+                    .gen_range(base_delay..=upper)
+                    .min(max_delay)
+            }
+        };
+
+        self.attempts += 1;
+        self.prev = delay;
+        // Delays too long for a Duration are capped as well
+        let longest = Duration::from_secs_f64(RetryConfig::MAX_DELAY);
+        Some(Duration::try_from_secs_f64(delay).map_or(longest, |delay| delay.min(longest)))
+    }
+
+    /// Applies a reloaded retry policy, keeping the number of attempts
+    pub fn set_policy(&mut self, policy: RetryConfig) {
+        if policy != self.policy {
+            self.policy = policy;
+            self.prev = policy.base_delay;
+        }
     }
 
-    /// Doubles the retry seconds, capping at 60 seconds
-    pub fn next(&mut self) -> u64 {
-        self.0 = self.0.min(60) * 2;
-        self.0
+    /// Returns the number of attempts since the last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempts
     }
 
-    /// Resets the retry seconds to the initial value of 1 second
+    /// Resets the backoff after a successful connection
     pub fn reset(&mut self) {
-        self.0 = 1;
+        self.attempts = 0;
+        self.prev = self.policy.base_delay;
     }
 }
@@ -0,0 +1,29 @@
+use anyhow::{Context as _, Result};
+use schemars::gen::SchemaSettings;
+use serde_json::json;
+
+use crate::{
+    capabilities::PROTOCOL_VERSION,
+    models::{ClientMessage, ConnectionErrorMessage, ServerMessage},
+};
+
+/// Returns the JSON Schema of every message exchanged with the server
+///
+/// The messages are defined in `definitions`, next to the types they use, and the protocol
+/// version is given as an annotation.
+pub fn export() -> Result<String> {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let messages = [
+        generator.subschema_for::<ServerMessage>(),
+        generator.subschema_for::<ClientMessage>(),
+        generator.subschema_for::<ConnectionErrorMessage>(),
+    ];
+    let schema = json!({
+        "$schema": generator.settings().meta_schema,
+        "title": "Remote Play Inviter messages",
+        "protocol": PROTOCOL_VERSION,
+        "anyOf": messages,
+        "definitions": generator.definitions(),
+    });
+    serde_json::to_string_pretty(&schema).context("Failed to serialize the schema")
+}
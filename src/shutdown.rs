@@ -0,0 +1,37 @@
+use tokio::signal::ctrl_c;
+
+/// Waits for a shutdown signal and returns its name
+#[cfg(unix)]
+pub async fn signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (Ok(mut terminate), Ok(mut hangup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) else {
+        let _ = ctrl_c().await;
+        return "SIGINT";
+    };
+
+    tokio::select! {
+        Ok(()) = ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+        _ = hangup.recv() => "SIGHUP",
+    }
+}
+
+/// Waits for a shutdown signal and returns its name
+#[cfg(windows)]
+pub async fn signal() -> &'static str {
+    use tokio::signal::windows::ctrl_close;
+
+    let Ok(mut close) = ctrl_close() else {
+        let _ = ctrl_c().await;
+        return "Ctrl+C";
+    };
+
+    tokio::select! {
+        Ok(()) = ctrl_c() => "Ctrl+C",
+        _ = close.recv() => "console closed",
+    }
+}
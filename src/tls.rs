@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use std::{fs, path::Path, sync::Arc};
+use tokio_tungstenite::Connector;
+
+use crate::config::TlsConfig;
+
+/// Builds the TLS connector for the settings, or `None` to use the default one
+pub fn connector(config: &TlsConfig) -> Result<Option<Connector>> {
+    if *config == TlsConfig::default() {
+        return Ok(None);
+    }
+
+    let builder = ClientConfig::builder();
+    let client_config = if config.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(
+                crypto::ring::default_provider().signature_verification_algorithms,
+            )))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(path) = &config.ca_file {
+            for cert in read_pem_certificates(path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid certificate in {:?}", path))?;
+            }
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(Some(Connector::Rustls(Arc::new(client_config))))
+}
+
+/// Reads the certificates of a PEM file
+pub fn read_pem_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read certificate file: {:?}", path))?;
+
+    let mut certs = Vec::new();
+    let mut lines = content.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line != "-----BEGIN CERTIFICATE-----" {
+            continue;
+        }
+        let base64: String = lines
+            .by_ref()
+            .take_while(|line| *line != "-----END CERTIFICATE-----")
+            .collect();
+        let der = BASE64
+            .decode(base64.as_bytes())
+            .with_context(|| format!("Invalid certificate in {:?}", path))?;
+        certs.push(CertificateDer::from(der));
+    }
+    if certs.is_empty() {
+        bail!("No certificate found in {:?}", path);
+    }
+    Ok(certs)
+}
+
+/// Verifier accepting any server certificate, still checking the handshake signatures
+#[derive(Debug)]
+struct NoVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
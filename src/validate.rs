@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use serde_json::Value as Json;
+use std::{
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tokio_tungstenite::tungstenite::http::Uri;
+use toml_edit::{ImDocument, Item, Value};
+
+use crate::{
+    client::Endpoint,
+    config::{resolve_schema, schema_types, Config, ConfigFile, EndpointConfig, LayeredConfig},
+    console,
+    proxy::Proxy,
+};
+
+/// Problem found in a configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    /// Line of the problem, starting at 1
+    pub line: usize,
+    /// Column of the problem, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        write!(f, "{path}:{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Checks the existing configuration files and the configuration they make together
+///
+/// Prints the problems found and returns their number.
+pub fn run(files: &[PathBuf], load: impl Fn() -> Result<LayeredConfig>) -> Result<usize> {
+    let mut problems = 0;
+    let mut checked = 0;
+    for path in files.iter().filter(|path| path.exists()) {
+        let diagnostics = check_file(path)?;
+        if diagnostics.is_empty() {
+            console::println!("✓ {}", path.display());
+        }
+        for diagnostic in &diagnostics {
+            console::eprintln!("☓ {}", diagnostic);
+        }
+        problems += diagnostics.len();
+        checked += 1;
+    }
+
+    // Values may be spread over several files, the environment and the command line
+    if problems == 0 {
+        let result: Result<()> = try {
+            let layers = load()?;
+            let config: Config = layers.get()?;
+            let endpoint: EndpointConfig = layers.get()?;
+            Endpoint::new(&endpoint, &config.uuid, 0)?;
+        };
+        if let Err(err) = result {
+            console::eprintln!("☓ {:#}", err);
+            problems += 1;
+        }
+    }
+
+    match problems {
+        0 => console::println!(
+            "✓ Configuration is valid ({} file{} checked)",
+            checked,
+            if checked == 1 { "" } else { "s" }
+        ),
+        1 => console::eprintln!("☓ Found 1 problem"),
+        n => console::eprintln!("☓ Found {} problems", n),
+    }
+    Ok(problems)
+}
+
+/// Checks a configuration file for syntax errors, unknown keys, type errors and invalid URLs
+pub fn check_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read config file: {:?}", path))?;
+    let schema = ConfigFile::schema()?;
+
+    let mut checker = Checker {
+        path,
+        content: &content,
+        definitions: &schema["definitions"],
+        diagnostics: Vec::new(),
+    };
+    match ImDocument::parse(content.as_str()) {
+        Ok(document) => {
+            let root = Item::Table(document.as_table().clone());
+            checker.check("", &root, None, &schema);
+        }
+        Err(err) => checker.report(err.span(), err.message().trim().to_string()),
+    }
+    Ok(checker.diagnostics)
+}
+
+/// Walks a parsed file along the schema of the configuration
+struct Checker<'a> {
+    path: &'a Path,
+    content: &'a str,
+    /// Schemas referenced by `$ref`
+    definitions: &'a Json,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    /// Checks a value and its children against the schema
+    ///
+    /// `key_span` is the span of the key holding the value, where unknown keys are reported.
+    fn check(&mut self, key: &str, item: &Item, key_span: Option<Range<usize>>, schema: &'a Json) {
+        let kind = kind(item);
+        let alternatives = resolve_schema(schema, self.definitions);
+        let matching: Vec<&'a Json> = (alternatives.iter().copied())
+            .filter(|schema| {
+                let types = schema_types(schema);
+                types.is_empty()
+                    || types.contains(&kind)
+                    || (kind == "integer" && types.contains(&"number"))
+            })
+            .collect();
+        let Some(schema) = matching.first().copied() else {
+            let expected: Vec<&str> = alternatives
+                .iter()
+                .flat_map(|schema| schema_types(schema))
+                .filter(|t| *t != "null")
+                .collect();
+            let span = item.span().or(key_span);
+            let message = format!(
+                "invalid type for `{key}`: expected {}, found {kind}",
+                expected.join(" or ")
+            );
+            self.report(span, message);
+            return;
+        };
+
+        match item {
+            Item::Table(_) | Item::Value(Value::InlineTable(_)) => {
+                let table = item.as_table_like().expect("table");
+                for (name, child) in table.iter() {
+                    let child_key = join(key, name);
+                    let child_span = table.get_key_value(name).and_then(|(k, _)| k.span());
+                    let additional = schema
+                        .get("additionalProperties")
+                        .filter(|additional| additional.is_object());
+                    match schema["properties"].get(name).or(additional) {
+                        Some(child_schema) => {
+                            self.check(&child_key, child, child_span, child_schema)
+                        }
+                        None => self.report(child_span, format!("unknown key `{child_key}`")),
+                    }
+                }
+            }
+            Item::ArrayOfTables(tables) => {
+                for table in tables.iter() {
+                    let child = Item::Table(table.clone());
+                    self.check(key, &child, table.span(), &schema["items"]);
+                }
+            }
+            Item::Value(Value::Array(array)) => {
+                for value in array.iter() {
+                    let child = Item::Value(value.clone());
+                    self.check(key, &child, value.span(), &schema["items"]);
+                }
+            }
+            Item::Value(value) => {
+                if let Some(message) = check_value(key, value, &matching) {
+                    self.report(value.span().or(key_span), message);
+                }
+            }
+            Item::None => (),
+        }
+    }
+
+    /// Records a problem at the start of the span
+    fn report(&mut self, span: Option<Range<usize>>, message: String) {
+        let offset = span.map_or(0, |span| span.start).min(self.content.len());
+        let before = &self.content[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        self.diagnostics.push(Diagnostic {
+            path: self.path.to_path_buf(),
+            line,
+            column,
+            message,
+        });
+    }
+}
+
+/// Checks the content of a value against the schemas matching its type, returning the problem found
+fn check_value(key: &str, value: &Value, schemas: &[&Json]) -> Option<String> {
+    // Enums are described by one schema per variant
+    let allowed: Vec<&str> = (schemas.iter())
+        .filter_map(|schema| schema.get("enum").and_then(Json::as_array))
+        .flatten()
+        .filter_map(Json::as_str)
+        .collect();
+    if !allowed.is_empty() && !value.as_str().is_some_and(|v| allowed.contains(&v)) {
+        return Some(format!(
+            "invalid value for `{key}`: expected one of {}",
+            allowed.join(", ")
+        ));
+    }
+    let number = (value.as_integer().map(|value| value as f64)).or(value.as_float());
+    if number.is_some_and(|number| !number.is_finite()) {
+        return Some(format!(
+            "invalid value for `{key}`: must be a finite number"
+        ));
+    }
+    let minimum = (schemas.iter())
+        .filter_map(|schema| schema.get("minimum").and_then(Json::as_f64))
+        .reduce(f64::min);
+    if let (Some(value), Some(minimum)) = (number, minimum) {
+        if value < minimum {
+            return Some(format!(
+                "invalid value for `{key}`: must be at least {minimum}"
+            ));
+        }
+    }
+    let maximum = (schemas.iter())
+        .filter_map(|schema| schema.get("maximum").and_then(Json::as_f64))
+        .reduce(f64::max);
+    if let (Some(value), Some(maximum)) = (number, maximum) {
+        if value > maximum {
+            return Some(format!(
+                "invalid value for `{key}`: must be at most {maximum}"
+            ));
+        }
+    }
+
+    let url = value.as_str()?;
+    match key.rsplit('.').next() {
+        Some("url") => {
+            let scheme = url.parse::<Uri>().ok().and_then(|uri| {
+                uri.host()?;
+                uri.scheme_str().map(str::to_string)
+            });
+            match scheme.as_deref() {
+                Some("ws" | "wss") => None,
+                _ => Some(format!(
+                    "invalid URL for `{key}`: expected ws://host or wss://host, found {url:?}"
+                )),
+            }
+        }
+        // An empty proxy connects directly
+        Some("proxy") if !url.is_empty() => Proxy::parse(url)
+            .err()
+            .map(|err| format!("invalid proxy for `{key}`: {err}")),
+        _ => None,
+    }
+}
+
+/// Returns the JSON Schema type of a TOML item
+fn kind(item: &Item) -> &'static str {
+    match item {
+        Item::Table(_) | Item::Value(Value::InlineTable(_)) => "object",
+        Item::ArrayOfTables(_) | Item::Value(Value::Array(_)) => "array",
+        Item::Value(Value::String(_)) => "string",
+        Item::Value(Value::Integer(_)) => "integer",
+        Item::Value(Value::Float(_)) => "number",
+        Item::Value(Value::Boolean(_)) => "boolean",
+        Item::Value(Value::Datetime(_)) => "datetime",
+        Item::None => "nothing",
+    }
+}
+
+/// Joins a dotted key and a child name
+fn join(key: &str, name: &str) -> String {
+    if key.is_empty() {
+        name.to_string()
+    } else {
+        format!("{key}.{name}")
+    }
+}
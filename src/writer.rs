@@ -0,0 +1,53 @@
+use anyhow::{Context as _, Result};
+use futures::SinkExt;
+use tokio::{
+    sync::mpsc::{channel, Receiver, Sender},
+    task::{self, JoinHandle},
+};
+use tokio_tungstenite::tungstenite::{protocol::Message, Error as WsError};
+
+use crate::models::ClientMessage;
+
+/// Number of messages that can be queued for the writer task
+const QUEUE_SIZE: usize = 64;
+
+/// Sender of the messages to write to the server
+pub type Outbox = Sender<Message>;
+
+/// Starts the task writing every message of the connection to the server
+///
+/// The task stops after writing a close frame or once every sender is dropped.
+pub fn spawn(
+    write: impl SinkExt<Message, Error = WsError> + Unpin + Send + 'static,
+) -> (Outbox, JoinHandle<Result<()>>) {
+    let (tx, rx) = channel::<Message>(QUEUE_SIZE);
+    (tx, task::spawn(write_messages(write, rx)))
+}
+
+/// Writes the queued messages to the server
+async fn write_messages(
+    mut write: impl SinkExt<Message, Error = WsError> + Unpin,
+    mut rx: Receiver<Message>,
+) -> Result<()> {
+    while let Some(message) = rx.recv().await {
+        let close = matches!(message, Message::Close(_));
+        write
+            .send(message)
+            .await
+            .context("Failed to send message to the server")?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Queues a JSON message for the server
+pub async fn send(tx: &Outbox, msg: &ClientMessage) -> Result<()> {
+    let text =
+        serde_json::to_string(msg).context("Failed to serialize JSON message for the server")?;
+    tx.send(Message::Text(text))
+        .await
+        .context("Failed to send message to the server")?;
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use crate::{console, ConnectionErrorMessage, ConnectionErrorType, VERSION};
+use crate::{
+    console,
+    models::{ConnectionErrorMessage, ConnectionErrorType},
+    VERSION,
+};
 use anyhow::{anyhow, Context as _, Result};
 use tokio_tungstenite::tungstenite::Error as WsError;
 
@@ -0,0 +1,71 @@
+use crate::{GameID, GameUID, SteamStuff};
+
+/// Callback for when a Remote Play invite result is received (invitee, guest_id, connect_url)
+pub type OnRemoteInvited = Box<dyn Fn(u64, u64, &str) + Send + Sync>;
+/// Callback for when a Remote Play session is started (invitee, guest_id)
+pub type OnRemoteStarted = Box<dyn Fn(u64, u64) + Send + Sync>;
+/// Callback for when a Remote Play session is closed (invitee, guest_id)
+pub type OnRemoteStopped = Box<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Remote Play backend
+///
+/// Implemented by the FFI [`SteamStuff`] and by [`crate::MockSteamStuff`] for headless testing.
+pub trait RemotePlayBackend: Send {
+    /// Dispatches pending callbacks
+    fn run_callbacks(&self);
+
+    /// Returns the game the host is currently running
+    fn get_running_game_id(&self) -> GameID;
+
+    /// Returns whether the game supports Remote Play Together
+    fn can_remote_play_together(&self, game_id: GameUID) -> bool;
+
+    /// Creates an invite and returns its guest ID (0 if the invite failed)
+    fn send_invite(&self, invitee: u64, game_id: GameUID) -> u64;
+
+    /// Cancels an invite or an active session
+    fn cancel_invite(&self, invitee: u64, guest_id: u64);
+
+    /// Sets the callback for invite results
+    fn set_on_remote_invited(&self, callback: OnRemoteInvited);
+
+    /// Sets the callback for started sessions
+    fn set_on_remote_started(&self, callback: OnRemoteStarted);
+
+    /// Sets the callback for closed sessions
+    fn set_on_remote_stopped(&self, callback: OnRemoteStopped);
+}
+
+impl RemotePlayBackend for SteamStuff {
+    fn run_callbacks(&self) {
+        SteamStuff::run_callbacks(self)
+    }
+
+    fn get_running_game_id(&self) -> GameID {
+        SteamStuff::get_running_game_id(self)
+    }
+
+    fn can_remote_play_together(&self, game_id: GameUID) -> bool {
+        SteamStuff::can_remote_play_together(self, game_id)
+    }
+
+    fn send_invite(&self, invitee: u64, game_id: GameUID) -> u64 {
+        SteamStuff::send_invite(self, invitee, game_id)
+    }
+
+    fn cancel_invite(&self, invitee: u64, guest_id: u64) {
+        SteamStuff::cancel_invite(self, invitee, guest_id)
+    }
+
+    fn set_on_remote_invited(&self, callback: OnRemoteInvited) {
+        SteamStuff::set_on_remote_invited(self, callback)
+    }
+
+    fn set_on_remote_started(&self, callback: OnRemoteStarted) {
+        SteamStuff::set_on_remote_started(self, callback)
+    }
+
+    fn set_on_remote_stopped(&self, callback: OnRemoteStopped) {
+        SteamStuff::set_on_remote_stopped(self, callback)
+    }
+}
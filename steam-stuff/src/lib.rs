@@ -1,8 +1,14 @@
+mod backend;
 mod game_id;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
 mod native;
 mod steam_stuff;
 
+pub use backend::{OnRemoteInvited, OnRemoteStarted, OnRemoteStopped, RemotePlayBackend};
 pub use game_id::{GameID, GameUID};
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{MockInvite, MockSteamStuff};
 pub use steam_stuff::SteamStuff;
 
 // extern crate to link C++ library
@@ -0,0 +1,227 @@
+use crate::backend::{OnRemoteInvited, OnRemoteStarted, OnRemoteStopped, RemotePlayBackend};
+use crate::{GameID, GameUID};
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+/// Base of the Steam IDs returned by `guest_steam_id`
+const MOCK_STEAM_ID_BASE: u64 = 76561197960265728;
+
+/// Event waiting to be dispatched by `run_callbacks`
+enum MockEvent {
+    Invited {
+        invitee: u64,
+        guest_id: u64,
+        connect_url: String,
+    },
+    Started {
+        invitee: u64,
+        guest_id: u64,
+    },
+    Stopped {
+        invitee: u64,
+        guest_id: u64,
+    },
+}
+
+/// An invite created through `send_invite`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockInvite {
+    /// Steam ID of the invitee (0 for an anonymous link)
+    pub invitee: u64,
+    /// Game the invite was created for
+    pub game_id: GameUID,
+    /// Guest ID returned to the caller
+    pub guest_id: u64,
+}
+
+struct MockState {
+    running_game: GameUID,
+    remote_play_together: bool,
+    respond_to_invites: bool,
+    next_guest_id: u64,
+    events: VecDeque<MockEvent>,
+    invites: Vec<MockInvite>,
+    cancelled: Vec<(u64, u64)>,
+    active: BTreeSet<(u64, u64)>,
+}
+
+#[derive(Default)]
+struct MockCallbacks {
+    on_remote_invited: Option<OnRemoteInvited>,
+    on_remote_started: Option<OnRemoteStarted>,
+    on_remote_stopped: Option<OnRemoteStopped>,
+}
+
+/// In-process Remote Play backend for running without a Steam client
+///
+/// Like Steam, callbacks are queued and only fired from `run_callbacks`.
+pub struct MockSteamStuff {
+    state: Mutex<MockState>,
+    callbacks: Mutex<MockCallbacks>,
+}
+
+impl Default for MockSteamStuff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockSteamStuff {
+    /// Creates a mock with no running game
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MockState {
+                running_game: GameID::new(0, 0, 0).into(),
+                remote_play_together: true,
+                respond_to_invites: true,
+                next_guest_id: 1,
+                events: VecDeque::new(),
+                invites: Vec::new(),
+                cancelled: Vec::new(),
+                active: BTreeSet::new(),
+            }),
+            callbacks: Mutex::new(MockCallbacks::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Sets the game reported as running
+    pub fn set_running_game(&self, game_id: GameID) {
+        self.state().running_game = game_id.into();
+    }
+
+    /// Sets whether the running game supports Remote Play Together
+    pub fn set_remote_play_together(&self, supported: bool) {
+        self.state().remote_play_together = supported;
+    }
+
+    /// Sets whether `send_invite` produces an invite URL (false simulates a failed invite result)
+    pub fn set_respond_to_invites(&self, respond: bool) {
+        self.state().respond_to_invites = respond;
+    }
+
+    /// Simulates a guest connecting to the session
+    pub fn simulate_join(&self, invitee: u64, guest_id: u64) {
+        let mut state = self.state();
+        state.active.insert((invitee, guest_id));
+        state.events.push_back(MockEvent::Started { invitee, guest_id });
+    }
+
+    /// Simulates a guest leaving the session
+    pub fn simulate_leave(&self, invitee: u64, guest_id: u64) {
+        let mut state = self.state();
+        state.active.remove(&(invitee, guest_id));
+        state.events.push_back(MockEvent::Stopped { invitee, guest_id });
+    }
+
+    /// Returns the Steam ID a mock guest joins with
+    pub fn guest_steam_id(guest_id: u64) -> u64 {
+        MOCK_STEAM_ID_BASE + guest_id
+    }
+
+    /// Returns every invite created so far
+    pub fn invites(&self) -> Vec<MockInvite> {
+        self.state().invites.clone()
+    }
+
+    /// Returns every (invitee, guest_id) passed to `cancel_invite`
+    pub fn cancelled(&self) -> Vec<(u64, u64)> {
+        self.state().cancelled.clone()
+    }
+}
+
+impl RemotePlayBackend for MockSteamStuff {
+    fn run_callbacks(&self) {
+        // Take the events first so that callbacks may call back into the mock
+        let events = std::mem::take(&mut self.state().events);
+        let callbacks = self.callbacks.lock().unwrap();
+        for event in events {
+            match event {
+                MockEvent::Invited {
+                    invitee,
+                    guest_id,
+                    connect_url,
+                } => {
+                    if let Some(cb) = &callbacks.on_remote_invited {
+                        cb(invitee, guest_id, &connect_url);
+                    }
+                }
+                MockEvent::Started { invitee, guest_id } => {
+                    if let Some(cb) = &callbacks.on_remote_started {
+                        cb(invitee, guest_id);
+                    }
+                }
+                MockEvent::Stopped { invitee, guest_id } => {
+                    if let Some(cb) = &callbacks.on_remote_stopped {
+                        cb(invitee, guest_id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_running_game_id(&self) -> GameID {
+        GameID::from(self.state().running_game)
+    }
+
+    fn can_remote_play_together(&self, _game_id: GameUID) -> bool {
+        self.state().remote_play_together
+    }
+
+    fn send_invite(&self, invitee: u64, game_id: GameUID) -> u64 {
+        let mut state = self.state();
+        if !GameID::from(game_id).is_valid_app() {
+            // Steam does not create invites for non-Steam games
+            return 0;
+        }
+
+        let guest_id = state.next_guest_id;
+        state.next_guest_id += 1;
+        state.invites.push(MockInvite {
+            invitee,
+            game_id,
+            guest_id,
+        });
+        if state.respond_to_invites {
+            state.events.push_back(MockEvent::Invited {
+                invitee,
+                guest_id,
+                connect_url: format!("https://s.team/p/MOCK-{guest_id:04}"),
+            });
+        }
+        guest_id
+    }
+
+    fn cancel_invite(&self, invitee: u64, guest_id: u64) {
+        let mut state = self.state();
+        state.cancelled.push((invitee, guest_id));
+
+        // Cancelling an active session disconnects the guest
+        let session = state
+            .active
+            .iter()
+            .find(|(_, id)| *id == guest_id)
+            .copied();
+        if let Some((invitee, guest_id)) = session {
+            state.active.remove(&(invitee, guest_id));
+            state
+                .events
+                .push_back(MockEvent::Stopped { invitee, guest_id });
+        }
+    }
+
+    fn set_on_remote_invited(&self, callback: OnRemoteInvited) {
+        self.callbacks.lock().unwrap().on_remote_invited = Some(callback);
+    }
+
+    fn set_on_remote_started(&self, callback: OnRemoteStarted) {
+        self.callbacks.lock().unwrap().on_remote_started = Some(callback);
+    }
+
+    fn set_on_remote_stopped(&self, callback: OnRemoteStopped) {
+        self.callbacks.lock().unwrap().on_remote_stopped = Some(callback);
+    }
+}
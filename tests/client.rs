@@ -0,0 +1,1265 @@
+mod common;
+
+use common::{FakeServer, TestClient};
+use remoteplay_inviter::{
+    capabilities,
+    client::{self, Exit},
+    config::{
+        AccessConfig, Config, GameConfig, GamesConfig, HeartbeatConfig, InviteConfig, Jitter,
+        LimitsConfig, RequestConfig, RetryConfig,
+    },
+    models::{ClientCmd, ErrorStatus, ServerCmd},
+    VERSION,
+};
+use steam_stuff::{GameID, MockSteamStuff};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+#[tokio::test]
+async fn connects_with_version_token_and_session() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let conn = server.accept().await;
+    assert_eq!(conn.uri.path(), "/ws");
+    assert_eq!(conn.query("v").as_deref(), Some(VERSION));
+    assert_eq!(conn.query("token").as_deref(), Some("test-token"));
+    assert_eq!(conn.query("session").as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn sends_hello_first() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let conn = server.accept().await;
+    let hello = conn.hello.unwrap();
+    assert_eq!(hello.id, None);
+    match hello.cmd {
+        ClientCmd::Hello {
+            protocol,
+            version,
+            commands,
+            os,
+            features,
+            ..
+        } => {
+            assert_eq!(protocol, capabilities::PROTOCOL_VERSION);
+            assert_eq!(version, VERSION);
+            assert!(commands.iter().any(|c| c == "link"));
+            assert!(os.starts_with(std::env::consts::OS));
+            assert!(features.iter().any(|f| f == "latency"));
+        }
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+}
+
+#[tokio::test]
+async fn records_server_capabilities() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    assert_eq!(client.handler.server_capabilities().protocol, 0);
+
+    let mut conn = server.accept().await;
+    conn.send_raw(
+        r#"{"cmd":"hello","protocol":7,"commands":["link"],"features":["latency","future"]}"#,
+    )
+    .await;
+    // The hello is handled before any later request
+    conn.request("1", ServerCmd::GameId).await;
+
+    let capabilities = client.handler.server_capabilities();
+    assert_eq!(capabilities.protocol, 7);
+    assert!(capabilities.supports_command("link"));
+    assert!(capabilities.supports_feature("latency"));
+    assert!(!capabilities.supports_feature("future"));
+    // The server lists its commands, and the latency is not one of them
+    let latency = ClientCmd::Latency {
+        current: 1,
+        average: 1,
+    };
+    assert!(!capabilities.accepts(&latency));
+
+    // A new connection starts over until the server answers again
+    conn.close("restarting").await;
+    server.accept_legacy().await;
+    assert_eq!(client.handler.server_capabilities().protocol, 0);
+}
+
+#[tokio::test]
+async fn syncs_empty_state_on_first_connection() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let conn = server.accept().await;
+    match conn.sync.unwrap().cmd {
+        ClientCmd::Sync {
+            resumed,
+            guests,
+            pending,
+        } => {
+            assert!(!resumed);
+            assert!(guests.is_empty());
+            assert!(pending.is_empty());
+        }
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+}
+
+#[tokio::test]
+async fn resyncs_guests_after_reconnect() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    // One connected guest and one pending invite
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", common::link(1086940)).await;
+    conn.request("2", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+
+    conn.close("restarting").await;
+    let conn = server.accept().await;
+    assert_eq!(conn.query("session").as_deref(), Some("1"));
+    match conn.sync.unwrap().cmd {
+        ClientCmd::Sync {
+            resumed,
+            guests,
+            pending,
+        } => {
+            assert!(resumed);
+            assert_eq!(guests.len(), 1);
+            assert_eq!(guests[0].guest_id, 1);
+            assert_eq!(guests[0].steam_id, steam_id.to_string());
+            assert_eq!(guests[0].user.as_ref().unwrap().id, "1000");
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].guest_id, 2);
+            assert_eq!(pending[0].user.as_ref().unwrap().name, "tester");
+        }
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+}
+
+#[tokio::test]
+async fn answers_pings() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    assert_eq!(conn.ping(b"hello").await, b"hello");
+}
+
+#[tokio::test]
+async fn game_id_reports_running_game() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert_eq!(res.id.as_deref(), Some("1"));
+    assert!(matches!(res.cmd, ClientCmd::GameId { game: 1086940, .. }));
+}
+
+#[tokio::test]
+async fn game_id_without_running_game() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InvalidApp
+        }
+    ));
+}
+
+#[tokio::test]
+async fn game_id_without_remote_play_together() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    {
+        let steam = client.steam.lock().await;
+        steam.set_running_game(GameID::new(1086940, 0, 0));
+        steam.set_remote_play_together(false);
+    }
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::UnsupportedApp
+        }
+    ));
+}
+
+#[tokio::test]
+async fn link_returns_invite_url() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", common::link(1086940)).await;
+    assert_eq!(res.id.as_deref(), Some("1"));
+    match res.cmd {
+        ClientCmd::Link { url, direct } => {
+            assert_eq!(url, "https://s.team/p/MOCK-0001");
+            assert!(!direct);
+        }
+        cmd => panic!("unexpected response: {cmd:?}"),
+    }
+
+    let invites = client.steam.lock().await.invites();
+    assert_eq!(invites.len(), 1);
+    assert_eq!(invites[0].invitee, 0);
+    assert_eq!(GameID::from(invites[0].game_id).app_id, 1086940);
+}
+
+#[tokio::test]
+async fn link_with_steam_id_sends_direct_invite() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    let friend = MockSteamStuff::guest_steam_id(42);
+
+    let mut conn = server.accept().await;
+    let link = ServerCmd::Link {
+        game: 1086940,
+        steam_id: Some(friend.to_string()),
+    };
+    let res = conn.request("1", link).await;
+    assert!(matches!(res.cmd, ClientCmd::Link { direct: true, .. }));
+    assert_eq!(client.steam.lock().await.invites()[0].invitee, friend);
+
+    // The pending direct invite is cancelled for the friend
+    let kick = ServerCmd::Kick {
+        guest: Some(1),
+        user_id: None,
+    };
+    conn.request("2", kick).await;
+    assert_eq!(client.steam.lock().await.cancelled(), vec![(friend, 1)]);
+}
+
+#[tokio::test]
+async fn link_rejects_invalid_steam_id() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    let link = ServerCmd::Link {
+        game: 1086940,
+        steam_id: Some("not-a-steam-id".to_string()),
+    };
+    let res = conn.request("1", link).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InvalidCmd
+        }
+    ));
+    assert!(client.steam.lock().await.invites().is_empty());
+}
+
+#[tokio::test]
+async fn blocked_game_is_not_shared() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        games: GamesConfig {
+            blocked: vec![1086940],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    let is_blocked = |cmd: &ClientCmd| {
+        matches!(
+            cmd,
+            ClientCmd::Error {
+                code: ErrorStatus::BlockedApp
+            }
+        )
+    };
+
+    let mut conn = server.accept().await;
+    assert!(is_blocked(&conn.request("1", ServerCmd::GameId).await.cmd));
+    let link = common::link(1086940);
+    assert!(is_blocked(&conn.request("2", link).await.cmd));
+    assert!(client.steam.lock().await.invites().is_empty());
+}
+
+#[tokio::test]
+async fn game_settings_apply_to_panel_and_links() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        games: GamesConfig {
+            allowed: vec![1086940],
+            overrides: vec![GameConfig {
+                app_id: 1086940,
+                max_guests: Some(1),
+                welcome: Some("Be nice!".to_string()),
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    let link = || common::link(1086940);
+
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert!(matches!(res.cmd, ClientCmd::GameId { welcome: Some(w), .. } if w == "Be nice!"));
+
+    // The game only takes one guest
+    conn.request("2", link()).await;
+    client
+        .steam
+        .lock()
+        .await
+        .simulate_join(MockSteamStuff::guest_steam_id(1), 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+    assert!(matches!(
+        conn.request("3", link()).await.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::Full
+        }
+    ));
+
+    // Other games are not in the allow list
+    let other = common::link(730);
+    assert!(matches!(
+        conn.request("4", other).await.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::BlockedApp
+        }
+    ));
+}
+
+#[tokio::test]
+async fn link_fails_for_non_steam_game() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", common::link(0)).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InviteFailed
+        }
+    ));
+}
+
+#[tokio::test]
+async fn link_times_out_without_invite_result() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            timeout: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    client.steam.lock().await.set_respond_to_invites(false);
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", common::link(1086940)).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InviteTimeout
+        }
+    ));
+    assert_eq!(client.steam.lock().await.cancelled(), vec![(0, 1)]);
+
+    // The client keeps serving requests afterwards
+    client.steam.lock().await.set_respond_to_invites(true);
+    let res = conn.request("2", common::link(1086940)).await;
+    match res.cmd {
+        ClientCmd::Link { url, .. } => assert_eq!(url, "https://s.team/p/MOCK-0002"),
+        cmd => panic!("unexpected response: {cmd:?}"),
+    }
+}
+
+#[tokio::test]
+async fn slow_link_does_not_block_other_requests() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            timeout: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    {
+        let steam = client.steam.lock().await;
+        steam.set_running_game(GameID::new(1086940, 0, 0));
+        steam.set_respond_to_invites(false);
+    }
+
+    let mut conn = server.accept().await;
+    conn.send(&common::request("1", common::link(1086940)))
+        .await;
+    assert_eq!(conn.ping(b"alive").await, b"alive");
+    let res = conn.request("2", ServerCmd::GameId).await;
+    assert_eq!(res.id.as_deref(), Some("2"));
+
+    // The stuck link is answered last
+    let res = conn.recv().await;
+    assert_eq!(res.id.as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn requests_over_in_flight_limit_are_rejected() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            timeout: 2,
+            ..Default::default()
+        },
+        requests: RequestConfig { max_in_flight: 1 },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    client.steam.lock().await.set_respond_to_invites(false);
+
+    let mut conn = server.accept().await;
+    conn.send(&common::request("1", common::link(1086940)))
+        .await;
+    let res = conn.request("2", common::link(1086940)).await;
+    assert_eq!(res.id.as_deref(), Some("2"));
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::Busy
+        }
+    ));
+}
+
+#[tokio::test]
+async fn guests_joining_do_not_disturb_requests() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.request("1", common::link(1086940)).await;
+    client
+        .steam
+        .lock()
+        .await
+        .simulate_join(MockSteamStuff::guest_steam_id(1), 1);
+
+    let res = conn.request("2", common::link(1086940)).await;
+    assert!(matches!(res.cmd, ClientCmd::Link { .. }));
+}
+
+#[tokio::test]
+async fn link_refused_when_host_is_full() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        limits: LimitsConfig {
+            max_guests: Some(1),
+            max_pending_links: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    let link = || common::link(1086940);
+    let is_full = |cmd: &ClientCmd| {
+        matches!(
+            cmd,
+            ClientCmd::Error {
+                code: ErrorStatus::Full
+            }
+        )
+    };
+
+    // Only one outstanding link at a time
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", link()).await;
+    assert!(is_full(&conn.request("2", link()).await.cmd));
+
+    // Only one connected guest at a time
+    client
+        .steam
+        .lock()
+        .await
+        .simulate_join(MockSteamStuff::guest_steam_id(1), 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+    assert!(is_full(&conn.request("3", link()).await.cmd));
+    assert_eq!(client.steam.lock().await.invites().len(), 1);
+}
+
+#[tokio::test]
+async fn link_refused_over_user_quota() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        limits: LimitsConfig {
+            max_links_per_user: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    let link = || common::link(1086940);
+
+    let mut conn = server.accept().await;
+    assert!(matches!(
+        conn.request("1", link()).await.cmd,
+        ClientCmd::Link { .. }
+    ));
+    assert!(matches!(
+        conn.request("2", link()).await.cmd,
+        ClientCmd::Link { .. }
+    ));
+    assert!(matches!(
+        conn.request("3", link()).await.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::QuotaExceeded
+        }
+    ));
+    assert_eq!(client.steam.lock().await.invites().len(), 2);
+}
+
+#[tokio::test]
+async fn pending_invites_count_towards_max_guests() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        limits: LimitsConfig {
+            max_guests: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+
+    // Requests processed concurrently cannot both take the last slot
+    let mut conn = server.accept().await;
+    conn.send(&common::request("1", common::link(1086940)))
+        .await;
+    conn.send(&common::request("2", common::link(1086940)))
+        .await;
+    let mut results = vec![conn.recv().await.cmd, conn.recv().await.cmd];
+    results.sort_by_key(|cmd| matches!(cmd, ClientCmd::Link { .. }));
+    assert!(matches!(
+        results[..],
+        [
+            ClientCmd::Error {
+                code: ErrorStatus::Full
+            },
+            ClientCmd::Link { .. }
+        ]
+    ));
+    assert_eq!(client.steam.lock().await.invites().len(), 1);
+}
+
+#[tokio::test]
+async fn failed_invites_release_their_slot() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            timeout: 1,
+            ..Default::default()
+        },
+        limits: LimitsConfig {
+            max_pending_links: Some(1),
+            max_links_per_user: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+
+    let mut conn = server.accept().await;
+    client.steam.lock().await.set_respond_to_invites(false);
+    assert!(matches!(
+        conn.request("1", common::link(1086940)).await.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InviteTimeout
+        }
+    ));
+
+    // Neither the pending limit nor the user quota count the failed invite
+    client.steam.lock().await.set_respond_to_invites(true);
+    assert!(matches!(
+        conn.request("2", common::link(1086940)).await.cmd,
+        ClientCmd::Link { .. }
+    ));
+}
+
+#[tokio::test]
+async fn unclaimed_invites_expire() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            expire_after: 1,
+            ..Default::default()
+        },
+        limits: LimitsConfig {
+            max_pending_links: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+
+    let mut conn = server.accept().await;
+    conn.request("1", common::link(1086940)).await;
+    assert!(matches!(
+        conn.request("2", common::link(1086940)).await.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::Full
+        }
+    ));
+
+    // The unclaimed link is cancelled and no longer counts
+    timeout(common::TIMEOUT, async {
+        while client.steam.lock().await.cancelled().is_empty() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(client.steam.lock().await.cancelled(), vec![(0, 1)]);
+    assert!(matches!(
+        conn.request("3", common::link(1086940)).await.cmd,
+        ClientCmd::Link { .. }
+    ));
+}
+
+#[tokio::test]
+async fn kick_by_guest_id_ends_the_session() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+
+    let kick = ServerCmd::Kick {
+        guest: Some(1),
+        user_id: None,
+    };
+    let res = conn.request("2", kick).await;
+    assert!(matches!(res.cmd, ClientCmd::Kick { guests } if guests == vec![1]));
+    assert_eq!(client.steam.lock().await.cancelled(), vec![(steam_id, 1)]);
+
+    // The guest is gone
+    let kick = ServerCmd::Kick {
+        guest: Some(1),
+        user_id: None,
+    };
+    let res = conn.request("3", kick).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::UnknownGuest
+        }
+    ));
+}
+
+#[tokio::test]
+async fn kick_by_discord_user_cancels_their_guests() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    // A connected guest and a pending invite claimed by the same user
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", common::link(1086940)).await;
+    conn.request("2", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+
+    let kick = ServerCmd::Kick {
+        guest: None,
+        user_id: Some("2000".to_string()),
+    };
+    let res = conn.request("3", kick).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::UnknownGuest
+        }
+    ));
+
+    let kick = ServerCmd::Kick {
+        guest: None,
+        user_id: Some("1000".to_string()),
+    };
+    let res = conn.request("4", kick).await;
+    assert!(matches!(res.cmd, ClientCmd::Kick { guests } if guests == vec![1, 2]));
+    let cancelled = client.steam.lock().await.cancelled();
+    assert!(cancelled.contains(&(steam_id, 1)));
+    assert!(cancelled.contains(&(0, 2)));
+}
+
+#[tokio::test]
+async fn players_lists_connected_guests() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    let res = conn.request("1", ServerCmd::Players).await;
+    assert!(matches!(res.cmd, ClientCmd::Players { players } if players.is_empty()));
+
+    // Only the guest that joined is listed, not the pending invite
+    conn.request("2", common::link(1086940)).await;
+    conn.request("3", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+    // Let the session last a second
+    sleep(Duration::from_secs(1)).await;
+
+    let res = conn.request("4", ServerCmd::Players).await;
+    let ClientCmd::Players { players } = res.cmd else {
+        panic!("unexpected response: {:?}", res.cmd);
+    };
+    assert_eq!(players.len(), 1);
+    assert_eq!(players[0].guest_id, 1);
+    assert_eq!(players[0].steam_id, steam_id.to_string());
+    let user = players[0].user.as_ref().unwrap();
+    assert_eq!((user.id.as_str(), user.name.as_str()), ("1000", "tester"));
+    assert!(players[0].joined_at > 0);
+    assert!(players[0].duration >= 1);
+}
+
+#[tokio::test]
+async fn pushes_join_and_leave_events() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.hello(&["events"]).await;
+    conn.request("1", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+
+    let event = conn.recv().await;
+    assert_eq!(event.id, None);
+    let ClientCmd::PlayerJoined { guest } = event.cmd else {
+        panic!("unexpected message: {:?}", event.cmd);
+    };
+    assert_eq!(guest.guest_id, 1);
+    assert_eq!(guest.steam_id, steam_id.to_string());
+    assert_eq!(guest.user.unwrap().id, "1000");
+
+    client.steam.lock().await.simulate_leave(steam_id, 1);
+    let event = conn.recv().await;
+    assert!(matches!(event.cmd, ClientCmd::PlayerLeft { guest } if guest.guest_id == 1));
+}
+
+#[tokio::test]
+async fn pushes_running_game_changes() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.hello(&["events"]).await;
+    conn.ping(b"sync").await;
+
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    let event = conn.recv().await;
+    assert_eq!(event.id, None);
+    assert!(matches!(
+        event.cmd,
+        ClientCmd::GameChanged {
+            game: Some(1086940),
+            previous: None
+        }
+    ));
+
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(0, 0, 0));
+    assert!(matches!(
+        conn.recv().await.cmd,
+        ClientCmd::GameChanged {
+            game: None,
+            previous: Some(1086940)
+        }
+    ));
+}
+
+#[tokio::test]
+async fn cancels_invites_of_closed_game() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        invite: InviteConfig {
+            cancel_on_game_change: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    conn.expect(|cmd| {
+        matches!(
+            cmd,
+            ClientCmd::GameChanged {
+                game: Some(1086940),
+                ..
+            }
+        )
+    })
+    .await;
+    conn.request("1", common::link(1086940)).await;
+    conn.request("2", common::link(730)).await;
+
+    // Only the invite of the game that was closed is cancelled
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(730, 0, 0));
+    conn.expect(|cmd| {
+        matches!(
+            cmd,
+            ClientCmd::GameChanged {
+                game: Some(730),
+                ..
+            }
+        )
+    })
+    .await;
+    assert_eq!(client.steam.lock().await.cancelled(), vec![(0, 1)]);
+    let res = conn.request("3", ServerCmd::Status).await;
+    assert!(matches!(res.cmd, ClientCmd::Status { pending: 1, .. }));
+}
+
+#[tokio::test]
+async fn no_events_unless_enabled_by_server() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.request("1", common::link(1086940)).await;
+    client
+        .steam
+        .lock()
+        .await
+        .simulate_join(MockSteamStuff::guest_steam_id(1), 1);
+
+    // The join is processed once the guest is listed
+    for id in 2.. {
+        let res = conn.request(&id.to_string(), ServerCmd::Players).await;
+        if matches!(res.cmd, ClientCmd::Players { players } if players.len() == 1) {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    let res = conn.request("0", ServerCmd::GameId).await;
+    assert_eq!(res.id.as_deref(), Some("0"));
+    assert!(conn.responses().iter().all(|res| res.id.is_some()));
+}
+
+#[tokio::test]
+async fn server_without_features_receives_no_optional_messages() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        heartbeat: HeartbeatConfig {
+            interval: 1,
+            timeout: 5,
+        },
+        ..Default::default()
+    };
+    let mut client = TestClient::start_with_config(server.url(), config).await;
+
+    // The server never answers the hello
+    let mut conn = server.accept_legacy().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert_eq!(res.id.as_deref(), Some("1"));
+
+    // A guest joins, the host switches games and a heartbeat is answered
+    client
+        .steam
+        .lock()
+        .await
+        .simulate_join(MockSteamStuff::guest_steam_id(1), 1);
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    conn.expect_ping().await;
+    conn.ping(b"flush").await;
+    let res = conn.request("2", ServerCmd::GameId).await;
+    assert_eq!(res.id.as_deref(), Some("2"));
+
+    client.shutdown();
+    conn.closed().await;
+    let responses = conn.responses();
+    assert_eq!(responses.len(), 2, "{responses:?}");
+    assert!(responses.iter().all(|res| res.id.is_some()));
+}
+
+#[tokio::test]
+async fn denied_user_is_forbidden() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        access: AccessConfig {
+            deny_users: vec!["1000".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(server.url(), config).await;
+
+    let mut conn = server.accept().await;
+    let link = common::link(1086940);
+    let res = conn.request("1", link).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::Forbidden
+        }
+    ));
+    assert!(client.steam.lock().await.invites().is_empty());
+}
+
+#[tokio::test]
+async fn only_allowed_guilds_are_served() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        access: AccessConfig {
+            allow_guilds: vec!["500".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let _client = TestClient::start_with_config(server.url(), config).await;
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", ServerCmd::Players).await;
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::Forbidden
+        }
+    ));
+
+    let mut msg = common::request("2", ServerCmd::Players);
+    msg.guild = Some("500".to_string());
+    conn.send(&msg).await;
+    assert!(matches!(conn.recv().await.cmd, ClientCmd::Players { .. }));
+}
+
+#[tokio::test]
+async fn status_describes_the_host() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    match conn.request("1", ServerCmd::Status).await.cmd {
+        ClientCmd::Status {
+            version,
+            connections,
+            reconnects,
+            game,
+            remote_play_together,
+            guests,
+            pending,
+            ..
+        } => {
+            assert_eq!(version, VERSION);
+            assert_eq!((connections, reconnects), (1, 0));
+            assert_eq!(game, None);
+            assert!(!remote_play_together);
+            assert_eq!((guests, pending), (0, 0));
+        }
+        cmd => panic!("unexpected response: {cmd:?}"),
+    }
+
+    // Start a game, create an invite and reconnect
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+    let link = common::link(1086940);
+    conn.request("2", link).await;
+    conn.close("restarting").await;
+
+    let mut conn = server.accept().await;
+    match conn.request("3", ServerCmd::Status).await.cmd {
+        ClientCmd::Status {
+            connections,
+            reconnects,
+            game,
+            remote_play_together,
+            pending,
+            ..
+        } => {
+            assert_eq!((connections, reconnects), (2, 1));
+            assert_eq!(game, Some(1086940));
+            assert!(remote_play_together);
+            assert_eq!(pending, 1);
+        }
+        cmd => panic!("unexpected response: {cmd:?}"),
+    }
+}
+
+#[tokio::test]
+async fn unknown_command_is_rejected() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.send_raw(r#"{"id":"1","cmd":"teleport"}"#).await;
+    let res = conn.recv().await;
+    assert_eq!(res.id.as_deref(), Some("1"));
+    assert!(matches!(
+        res.cmd,
+        ClientCmd::Error {
+            code: ErrorStatus::InvalidCmd
+        }
+    ));
+    assert_eq!(conn.responses().len(), 1);
+}
+
+#[tokio::test]
+async fn exit_stops_the_client() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.send(&common::request("1", ServerCmd::Exit)).await;
+    assert!(matches!(conn.recv().await.cmd, ClientCmd::Offline { .. }));
+    conn.closed().await;
+    assert_eq!(client.exited().await.unwrap(), Exit::Server);
+}
+
+#[tokio::test]
+async fn reconnects_after_close() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    client
+        .steam
+        .lock()
+        .await
+        .set_running_game(GameID::new(1086940, 0, 0));
+
+    let mut conn = server.accept().await;
+    conn.close("restarting").await;
+
+    let mut conn = server.accept().await;
+    let res = conn.request("1", ServerCmd::GameId).await;
+    assert!(matches!(res.cmd, ClientCmd::GameId { game: 1086940, .. }));
+}
+
+#[tokio::test]
+async fn reconnects_after_malformed_frame() {
+    let mut server = FakeServer::start().await;
+    let _client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.send_raw("{not json").await;
+    conn.closed().await;
+
+    let mut conn = server.accept().await;
+    assert_eq!(conn.ping(b"again").await, b"again");
+}
+
+#[tokio::test]
+async fn rejected_connection_stops_the_client() {
+    let server = FakeServer::start().await;
+    server.reject_next(r#"{"error":"banned","message":"This client is banned"}"#);
+    let client = TestClient::start(server.url()).await;
+
+    assert_eq!(client.exited().await.unwrap(), Exit::Disconnected);
+}
+
+#[tokio::test]
+async fn heartbeat_reports_latency() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        heartbeat: HeartbeatConfig {
+            interval: 1,
+            timeout: 5,
+        },
+        ..Default::default()
+    };
+    let _client = TestClient::start_with_config(server.url(), config).await;
+
+    // The pong is sent automatically while waiting for the report
+    let mut conn = server.accept().await;
+    let res = conn.recv().await;
+    assert_eq!(res.id, None);
+    assert!(matches!(res.cmd, ClientCmd::Latency { .. }));
+    assert!(conn.received.iter().any(|m| matches!(m, Message::Ping(_))));
+}
+
+#[tokio::test]
+async fn reconnects_when_heartbeat_is_unanswered() {
+    let mut server = FakeServer::start().await;
+    let config = Config {
+        heartbeat: HeartbeatConfig {
+            interval: 1,
+            timeout: 1,
+        },
+        ..Default::default()
+    };
+    let _client = TestClient::start_with_config(server.url(), config).await;
+
+    // Stop reading after the first ping so that no pong is ever sent
+    let mut conn = server.accept().await;
+    conn.expect_ping().await;
+
+    // The client gives up on the silent connection and reconnects
+    server.accept().await;
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    // Nothing listens on the port once the listener is dropped
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let url = client::build_url(&format!("ws://127.0.0.1:{port}"), "test-token", 1).unwrap();
+    let config = Config {
+        retry: RetryConfig {
+            base_delay: 0.1,
+            jitter: Jitter::None,
+            max_attempts: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = TestClient::start_with_config(url, config).await;
+
+    assert_eq!(client.exited().await.unwrap(), Exit::Disconnected);
+}
+
+#[tokio::test]
+async fn reloaded_retry_settings_apply_to_the_next_attempt() {
+    let mut server = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+    let mut conn = server.accept().await;
+
+    let config = Config {
+        retry: RetryConfig {
+            max_attempts: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    client.handler.reload_config(config);
+    conn.close("restarting").await;
+
+    assert_eq!(client.exited().await.unwrap(), Exit::Disconnected);
+}
+
+#[tokio::test]
+async fn shutdown_disconnects_guests_and_notifies_server() {
+    let mut server = FakeServer::start().await;
+    let mut client = TestClient::start(server.url()).await;
+
+    // One connected guest and one pending invite
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", common::link(1086940)).await;
+    conn.request("2", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+
+    client.shutdown();
+    let offline = conn.expect(|cmd| matches!(cmd, ClientCmd::Offline { .. }));
+    match offline.await.cmd {
+        ClientCmd::Offline { reason } => assert!(reason.contains("shutting down")),
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+    conn.closed().await;
+
+    let steam = client.steam.clone();
+    assert_eq!(client.exited().await.unwrap(), Exit::Signal);
+    let cancelled = steam.lock().await.cancelled();
+    assert!(cancelled.contains(&(steam_id, 1)));
+    assert!(cancelled.contains(&(0, 2)));
+}
+
+#[tokio::test]
+async fn endpoint_change_switches_servers_and_keeps_guests() {
+    let mut server = FakeServer::start().await;
+    let mut other = FakeServer::start().await;
+    let client = TestClient::start(server.url()).await;
+
+    let mut conn = server.accept().await;
+    conn.enable_events().await;
+    conn.request("1", common::link(1086940)).await;
+    let steam_id = MockSteamStuff::guest_steam_id(1);
+    client.steam.lock().await.simulate_join(steam_id, 1);
+    conn.expect(|cmd| matches!(cmd, ClientCmd::PlayerJoined { .. }))
+        .await;
+
+    client
+        .endpoint
+        .send_modify(|endpoint| endpoint.url = other.url());
+    match conn.recv().await.cmd {
+        ClientCmd::Offline { reason } => assert!(reason.contains("switching")),
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+    conn.closed().await;
+
+    // The new server learns about the guest that stayed, in a session it has never seen
+    let conn = other.accept().await;
+    match conn.sync.unwrap().cmd {
+        ClientCmd::Sync {
+            resumed, guests, ..
+        } => {
+            assert!(!resumed);
+            assert_eq!(guests.len(), 1);
+        }
+        cmd => panic!("unexpected message: {cmd:?}"),
+    }
+    assert!(client.steam.lock().await.cancelled().is_empty());
+}
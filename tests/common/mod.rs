@@ -0,0 +1,369 @@
+//! Fake Discord bot server speaking the daemon WebSocket protocol
+
+#![allow(dead_code)]
+
+pub mod proxy;
+pub mod temp;
+
+use futures::SinkExt;
+use futures_util::stream::StreamExt;
+use remoteplay_inviter::{
+    capabilities,
+    client::{self, Endpoint, Exit},
+    config::Config,
+    handlers::Handler,
+    models::{ClientCmd, ClientMessage, ServerCmd, ServerMessage, User},
+    proxy::Proxy,
+};
+use std::sync::{Arc, Mutex};
+use steam_stuff::{MockSteamStuff, RemotePlayBackend};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot, watch, Mutex as AsyncMutex},
+    task::JoinHandle,
+    time::{timeout, Duration},
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{Request, Response},
+        http::{StatusCode, Uri},
+        protocol::{frame::coding::CloseCode, CloseFrame, Message},
+    },
+    WebSocketStream,
+};
+
+/// How long to wait for the client before failing a test
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Features enabled by the fake server unless a test answers the hello itself
+pub const SERVER_FEATURES: &[&str] = &["latency", "offline", "sync"];
+
+/// Local WebSocket server standing in for the Discord bot
+pub struct FakeServer {
+    port: u16,
+    reject: Arc<Mutex<Option<String>>>,
+    connections: mpsc::Receiver<FakeConnection>,
+}
+
+impl FakeServer {
+    /// Starts the server on a random local port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reject = Arc::new(Mutex::new(None::<String>));
+        let (tx, connections) = mpsc::channel(8);
+
+        let reject_clone = reject.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let reject = reject_clone.lock().unwrap().take();
+                let mut uri = None;
+                let callback = |req: &Request, res: Response| {
+                    uri = Some(req.uri().clone());
+                    match reject {
+                        // Refuse the handshake like the bot does for outdated clients
+                        Some(error) => Err(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .header("X-Error", error)
+                            .body(None)
+                            .unwrap()),
+                        None => Ok(res),
+                    }
+                };
+                if let Ok(ws) = accept_hdr_async(stream, callback).await {
+                    let connection = FakeConnection {
+                        ws,
+                        uri: uri.unwrap(),
+                        hello: None,
+                        sync: None,
+                        received: Vec::new(),
+                    };
+                    if tx.send(connection).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            port,
+            reject,
+            connections,
+        }
+    }
+
+    /// Returns the endpoint URL to pass to `client::build_url`
+    pub fn endpoint_url(&self) -> String {
+        format!("ws://127.0.0.1:{}", self.port)
+    }
+
+    /// Returns a complete WebSocket URL for `client::run`
+    pub fn url(&self) -> String {
+        client::build_url(&self.endpoint_url(), "test-token", 1).unwrap()
+    }
+
+    /// Rejects the next handshake with a 400 response carrying the `X-Error` header
+    pub fn reject_next(&self, error: &str) {
+        *self.reject.lock().unwrap() = Some(error.to_string());
+    }
+
+    /// Waits for the next accepted connection, answers its hello with `SERVER_FEATURES` and
+    /// waits for its sync
+    pub async fn accept(&mut self) -> FakeConnection {
+        let mut conn = self.accept_legacy().await;
+        conn.hello(SERVER_FEATURES).await;
+        let sync = conn.recv().await;
+        assert!(matches!(sync.cmd, ClientCmd::Sync { .. }));
+        conn.received.clear();
+        conn.sync = Some(sync);
+        conn
+    }
+
+    /// Waits for the next accepted connection and its hello, left unanswered like the servers
+    /// predating the handshake do
+    pub async fn accept_legacy(&mut self) -> FakeConnection {
+        let mut conn = timeout(TIMEOUT, self.connections.recv())
+            .await
+            .expect("client did not connect")
+            .unwrap();
+        let hello = conn.recv().await;
+        assert!(matches!(hello.cmd, ClientCmd::Hello { .. }));
+        conn.received.clear();
+        conn.hello = Some(hello);
+        conn
+    }
+}
+
+/// A client connection accepted by the fake server
+pub struct FakeConnection {
+    ws: WebSocketStream<TcpStream>,
+    /// Request URI of the handshake
+    pub uri: Uri,
+    /// Hello sent by the client when connecting
+    pub hello: Option<ClientMessage>,
+    /// State snapshot sent by the client after the server hello
+    pub sync: Option<ClientMessage>,
+    /// Every frame received from the client
+    pub received: Vec<Message>,
+}
+
+impl FakeConnection {
+    /// Returns the value of a query parameter of the handshake URI
+    pub fn query(&self, key: &str) -> Option<String> {
+        self.uri.query()?.split('&').find_map(|pair| {
+            let (k, v) = pair.split_once('=')?;
+            (k == key).then(|| v.to_string())
+        })
+    }
+
+    /// Sends a request to the client
+    pub async fn send(&mut self, msg: &ServerMessage) {
+        let text = serde_json::to_string(msg).unwrap();
+        self.send_raw(&text).await;
+    }
+
+    /// Answers the client hello with the given features
+    pub async fn hello(&mut self, features: &[&str]) {
+        let hello = ServerMessage {
+            id: String::new(),
+            user: None,
+            guild: None,
+            channel: None,
+            cmd: ServerCmd::Hello {
+                protocol: capabilities::PROTOCOL_VERSION,
+                commands: Vec::new(),
+                features: features.iter().map(|s| s.to_string()).collect(),
+            },
+        };
+        self.send(&hello).await;
+    }
+
+    /// Sends a text frame as is, e.g. malformed JSON
+    pub async fn send_raw(&mut self, text: &str) {
+        self.ws.send(Message::Text(text.to_string())).await.unwrap();
+    }
+
+    /// Waits for the next ping from the client without answering it
+    pub async fn expect_ping(&mut self) -> Vec<u8> {
+        loop {
+            if let Message::Ping(ping) = self.next().await.expect("connection closed") {
+                return ping;
+            }
+        }
+    }
+
+    /// Sends a ping and waits for the matching pong
+    pub async fn ping(&mut self, payload: &[u8]) -> Vec<u8> {
+        self.ws.send(Message::Ping(payload.to_vec())).await.unwrap();
+        loop {
+            if let Message::Pong(pong) = self.next().await.expect("connection closed") {
+                return pong;
+            }
+        }
+    }
+
+    /// Sends a close frame with a reason
+    pub async fn close(&mut self, reason: &str) {
+        let frame = CloseFrame {
+            code: CloseCode::Normal,
+            reason: reason.to_string().into(),
+        };
+        let _ = self.ws.close(Some(frame)).await;
+    }
+
+    /// Waits for the next frame from the client and records it
+    pub async fn next(&mut self) -> Option<Message> {
+        let message = timeout(TIMEOUT, self.ws.next())
+            .await
+            .expect("client did not respond")?
+            .ok()?;
+        self.received.push(message.clone());
+        Some(message)
+    }
+
+    /// Waits for the next JSON message from the client
+    pub async fn recv(&mut self) -> ClientMessage {
+        loop {
+            match self.next().await.expect("connection closed") {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                Message::Close(_) => panic!("connection closed"),
+                _ => (),
+            }
+        }
+    }
+
+    /// Sends a request and waits for the response, skipping the events sent meanwhile
+    pub async fn request(&mut self, id: &str, cmd: ServerCmd) -> ClientMessage {
+        self.send(&request(id, cmd)).await;
+        loop {
+            let res = self.recv().await;
+            if res.id.is_some() {
+                return res;
+            }
+        }
+    }
+
+    /// Waits for the next message matching, skipping the others
+    pub async fn expect(&mut self, matches: impl Fn(&ClientCmd) -> bool) -> ClientMessage {
+        loop {
+            let msg = self.recv().await;
+            if matches(&msg.cmd) {
+                return msg;
+            }
+        }
+    }
+
+    /// Enables the events along with `SERVER_FEATURES`, but the sync that would send another snapshot
+    pub async fn enable_events(&mut self) {
+        self.hello(&["latency", "offline", "events"]).await;
+    }
+
+    /// Waits until the client closes the connection, acknowledging its close frame
+    pub async fn closed(&mut self) {
+        while self.next().await.is_some() {}
+    }
+
+    /// Returns the JSON messages received so far
+    pub fn responses(&self) -> Vec<ClientMessage> {
+        self.received
+            .iter()
+            .filter_map(|m| match m {
+                Message::Text(text) => serde_json::from_str(text).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Builds a request from the test user
+pub fn request(id: &str, cmd: ServerCmd) -> ServerMessage {
+    ServerMessage {
+        id: id.to_string(),
+        user: Some(User {
+            id: "1000".to_string(),
+            name: "tester".to_string(),
+        }),
+        guild: None,
+        channel: None,
+        cmd,
+    }
+}
+
+/// Builds a request for an invite link to the game
+pub fn link(game: u32) -> ServerCmd {
+    ServerCmd::Link {
+        game,
+        steam_id: None,
+    }
+}
+
+/// Client running against a mock Remote Play backend
+pub struct TestClient {
+    pub steam: Arc<AsyncMutex<MockSteamStuff>>,
+    pub handler: Arc<Handler>,
+    pub task: JoinHandle<anyhow::Result<Exit>>,
+    /// Endpoint the client connects to, changed to make it switch servers
+    pub endpoint: watch::Sender<Endpoint>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestClient {
+    /// Starts the client connected to `url` with the default configuration
+    pub async fn start(url: String) -> Self {
+        Self::start_with_config(url, Config::default()).await
+    }
+
+    /// Starts the client connected to `url`
+    pub async fn start_with_config(url: String, config: Config) -> Self {
+        Self::spawn(url, None, config).await
+    }
+
+    /// Starts the client connected to `url` through `proxy`
+    pub async fn start_with_proxy(url: String, proxy: Proxy) -> Self {
+        Self::spawn(url, Some(proxy), Config::default()).await
+    }
+
+    async fn spawn(url: String, proxy: Option<Proxy>, config: Config) -> Self {
+        let steam = Arc::new(AsyncMutex::new(MockSteamStuff::new()));
+        let backend: Arc<AsyncMutex<dyn RemotePlayBackend>> = steam.clone();
+
+        let handler = Arc::new(Handler::new(backend, config));
+        handler.setup_steam_callbacks().await;
+        handler.run_steam_callbacks();
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let shutdown = async move {
+            let _ = shutdown_rx.await;
+            "test"
+        };
+        let (endpoint, endpoints) = watch::channel(Endpoint {
+            url,
+            proxy,
+            tls: None,
+        });
+        let task = tokio::spawn(client::run(endpoints, handler.clone(), shutdown));
+        Self {
+            steam,
+            handler,
+            task,
+            endpoint,
+            shutdown: Some(shutdown_tx),
+        }
+    }
+
+    /// Simulates a shutdown signal
+    pub fn shutdown(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+
+    /// Waits for the client to exit
+    pub async fn exited(self) -> anyhow::Result<Exit> {
+        timeout(TIMEOUT, self.task)
+            .await
+            .expect("client did not exit")
+            .unwrap()
+    }
+}
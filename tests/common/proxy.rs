@@ -0,0 +1,195 @@
+//! Local HTTP CONNECT and SOCKS5 proxies standing in for corporate proxies
+
+use data_encoding::BASE64;
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+/// Proxy protocol spoken by the fake proxy
+#[derive(Clone, Copy)]
+enum Protocol {
+    Http,
+    Socks5,
+}
+
+/// Local proxy forwarding tunnels to their target
+pub struct FakeProxy {
+    port: u16,
+    /// Targets requested by the client, as `host:port`
+    pub targets: Arc<Mutex<Vec<String>>>,
+    /// One permit per refused handshake
+    refused: Arc<Semaphore>,
+}
+
+impl FakeProxy {
+    /// Starts an HTTP CONNECT proxy, requiring the credentials if given
+    pub async fn http(auth: Option<(&str, &str)>) -> Self {
+        Self::start(Protocol::Http, auth).await
+    }
+
+    /// Starts a SOCKS5 proxy, requiring the credentials if given
+    pub async fn socks5(auth: Option<(&str, &str)>) -> Self {
+        Self::start(Protocol::Socks5, auth).await
+    }
+
+    async fn start(protocol: Protocol, auth: Option<(&str, &str)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let auth = auth.map(|(user, pass)| (user.to_string(), pass.to_string()));
+
+        let refused = Arc::new(Semaphore::new(0));
+
+        let (targets_clone, refused_clone) = (targets.clone(), refused.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (targets, refused) = (targets_clone.clone(), refused_clone.clone());
+                let auth = auth.clone();
+                tokio::spawn(async move {
+                    let tunnel = match protocol {
+                        Protocol::Http => http_handshake(stream, auth).await,
+                        Protocol::Socks5 => socks5_handshake(stream, auth).await,
+                    };
+                    let Some((mut client, target)) = tunnel else {
+                        refused.add_permits(1);
+                        return;
+                    };
+                    targets.lock().unwrap().push(target.clone());
+                    if let Ok(mut upstream) = TcpStream::connect(&target).await {
+                        let _ = copy_bidirectional(&mut client, &mut upstream).await;
+                    }
+                });
+            }
+        });
+
+        Self {
+            port,
+            targets,
+            refused,
+        }
+    }
+
+    /// Returns the proxy URL with the given scheme and credentials
+    pub fn url(&self, scheme: &str, auth: Option<(&str, &str)>) -> String {
+        match auth {
+            Some((user, pass)) => format!("{scheme}://{user}:{pass}@127.0.0.1:{}", self.port),
+            None => format!("{scheme}://127.0.0.1:{}", self.port),
+        }
+    }
+
+    /// Returns the targets requested so far
+    pub fn targets(&self) -> Vec<String> {
+        self.targets.lock().unwrap().clone()
+    }
+
+    /// Waits until the proxy refused the given number of handshakes
+    pub async fn refused(&self, count: u32) {
+        let permits = tokio::time::timeout(super::TIMEOUT, self.refused.acquire_many(count))
+            .await
+            .expect("proxy did not refuse the handshakes")
+            .unwrap();
+        permits.forget();
+    }
+}
+
+/// Handles a CONNECT request and returns the stream with its target
+async fn http_handshake(
+    mut stream: TcpStream,
+    auth: Option<(String, String)>,
+) -> Option<(TcpStream, String)> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.ok()?);
+    }
+    let head = String::from_utf8(head).ok()?;
+    let mut lines = head.lines();
+    let target = lines
+        .next()?
+        .strip_prefix("CONNECT ")?
+        .split_whitespace()
+        .next()?
+        .to_string();
+
+    if let Some((user, pass)) = auth {
+        let expected = format!(
+            "Basic {}",
+            BASE64.encode(format!("{user}:{pass}").as_bytes())
+        );
+        let authorized = lines.any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.eq_ignore_ascii_case("Proxy-Authorization") && value.trim() == expected
+            })
+        });
+        if !authorized {
+            let _ = stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await;
+            return None;
+        }
+    }
+
+    stream
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await
+        .ok()?;
+    Some((stream, target))
+}
+
+/// Handles a SOCKS5 greeting and CONNECT request and returns the stream with its target
+async fn socks5_handshake(
+    mut stream: TcpStream,
+    auth: Option<(String, String)>,
+) -> Option<(TcpStream, String)> {
+    // Method negotiation
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.ok()?;
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await.ok()?;
+    let method = if auth.is_some() { 0x02 } else { 0x00 };
+    if header[0] != 0x05 || !methods.contains(&method) {
+        let _ = stream.write_all(&[0x05, 0xFF]).await;
+        return None;
+    }
+    stream.write_all(&[0x05, method]).await.ok()?;
+
+    // Username/password authentication
+    if let Some((user, pass)) = auth {
+        let mut read_field = [0u8; 2];
+        stream.read_exact(&mut read_field).await.ok()?;
+        let mut given_user = vec![0u8; read_field[1] as usize];
+        stream.read_exact(&mut given_user).await.ok()?;
+        let mut given_pass = vec![0u8; stream.read_u8().await.ok()? as usize];
+        stream.read_exact(&mut given_pass).await.ok()?;
+        if given_user != user.as_bytes() || given_pass != pass.as_bytes() {
+            let _ = stream.write_all(&[0x01, 0x01]).await;
+            return None;
+        }
+        stream.write_all(&[0x01, 0x00]).await.ok()?;
+    }
+
+    // Connection request
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await.ok()?;
+    let host = match request[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await.ok()?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        0x03 => {
+            let mut name = vec![0u8; stream.read_u8().await.ok()? as usize];
+            stream.read_exact(&mut name).await.ok()?;
+            String::from_utf8(name).ok()?
+        }
+        _ => return None,
+    };
+    let port = stream.read_u16().await.ok()?;
+    stream
+        .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+        .ok()?;
+    Some((stream, format!("{host}:{port}")))
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::temp::TempDir;
+use remoteplay_inviter::{
+    args::{Args, Command, IdentityCommand},
+    config::{self, Config, LayeredConfig, Source},
+    identity,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const UUID: &str = "0b7e2a59-4bb4-4c5e-9d3e-6a0f6f3c2f11";
+
+/// Loads the defaults and a configuration file holding the UUID
+fn layers(dir: &Path) -> (LayeredConfig, PathBuf) {
+    let path = dir.join("config.toml");
+    if !path.exists() {
+        fs::write(&path, format!("# Host settings\nuuid = \"{UUID}\"\n")).unwrap();
+    }
+    let mut layers = LayeredConfig::default();
+    layers.merge(
+        toml::Table::try_from(Config::default()).unwrap(),
+        &Source::Default,
+    );
+    layers.merge_file(&path).unwrap();
+    (layers, path)
+}
+
+fn uuid(layers: &LayeredConfig) -> String {
+    layers.get::<Config>().unwrap().uuid
+}
+
+#[test]
+fn regenerates_the_uuid_in_place() {
+    let dir = TempDir::new();
+    let (mut layers, path) = layers(&dir);
+
+    identity::run(&IdentityCommand::Regenerate, &mut layers).unwrap();
+    let regenerated = uuid(&layers);
+    assert_ne!(regenerated, UUID);
+
+    // The file keeps its comments and only holds the new UUID
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.contains("# Host settings"));
+    assert!(content.contains(&regenerated));
+    assert!(!content.contains(UUID));
+    assert_eq!(uuid(&self::layers(&dir).0), regenerated);
+}
+
+#[test]
+fn exports_and_imports_the_uuid() {
+    let (dir, other_dir) = (TempDir::new(), TempDir::new());
+    let (mut layers, _) = layers(&dir);
+    let export = dir.join("identity.toml");
+    identity::run(&IdentityCommand::Export(export.clone()), &mut layers).unwrap();
+    assert_eq!(identity::read_identity(&export).unwrap(), UUID);
+
+    // Another host takes over the identity
+    let (mut other, other_path) = self::layers(&other_dir);
+    identity::run(&IdentityCommand::Regenerate, &mut other).unwrap();
+    identity::run(&IdentityCommand::Import(export), &mut other).unwrap();
+    assert_eq!(uuid(&other), UUID);
+    assert!(fs::read_to_string(other_path).unwrap().contains(UUID));
+}
+
+#[test]
+fn imports_plain_uuids_only() {
+    let dir = TempDir::new();
+    let plain = dir.join("plain.txt");
+    fs::write(&plain, format!("{}\n", UUID.to_uppercase())).unwrap();
+    assert_eq!(identity::read_identity(&plain).unwrap(), UUID);
+
+    let invalid = dir.join("invalid.txt");
+    fs::write(&invalid, "not-a-uuid").unwrap();
+    assert!(identity::read_identity(&invalid).is_err());
+
+    let (mut layers, _) = layers(&dir);
+    assert!(identity::run(&IdentityCommand::Import(invalid), &mut layers).is_err());
+    assert_eq!(uuid(&layers), UUID);
+}
+
+#[test]
+fn uuid_from_environment_is_not_saved() {
+    let dir = TempDir::new();
+    let (mut layers, _) = layers(&dir);
+    layers
+        .merge_env([("REMOTEPLAY_UUID".to_string(), UUID.to_string())])
+        .unwrap();
+    assert!(config::save_uuid(&mut layers, "other").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn identity_files_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let (mut layers, path) = layers(&dir);
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(config::is_world_readable(&path));
+
+    // Existing files keep their permissions, the user is warned instead
+    identity::run(&IdentityCommand::Regenerate, &mut layers).unwrap();
+    assert!(config::is_world_readable(&path));
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o644
+    );
+
+    let export = dir.join("identity.toml");
+    identity::run(&IdentityCommand::Export(export.clone()), &mut layers).unwrap();
+    let mode = fs::metadata(&export).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // Only the written files are left in the directory
+    let mut names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [path.file_name().unwrap(), export.file_name().unwrap()]
+    );
+}
+
+#[test]
+fn parses_identity_commands() {
+    let parse = |args: &[&str]| Args::parse(args.iter().map(|s| s.to_string()));
+    let identity = |command| Some(Command::Identity(command));
+
+    assert_eq!(parse(&[]).unwrap().command, None);
+    assert_eq!(
+        parse(&["identity"]).unwrap().command,
+        identity(IdentityCommand::Show)
+    );
+    assert_eq!(
+        parse(&["--profile", "staging", "identity", "regenerate"])
+            .unwrap()
+            .command,
+        identity(IdentityCommand::Regenerate)
+    );
+    assert_eq!(
+        parse(&["identity", "export", "id.toml"]).unwrap().command,
+        identity(IdentityCommand::Export("id.toml".into()))
+    );
+    assert!(parse(&["identity", "import"]).is_err());
+    assert!(parse(&["identities"]).is_err());
+}
@@ -0,0 +1,156 @@
+//! Wire format of the messages exchanged with the server
+//!
+//! Each message is parsed and serialized back, so any change to the serde attributes that
+//! alters the JSON seen by the bot fails here.
+
+use remoteplay_inviter::{
+    capabilities,
+    models::{
+        ClientMessage, ConnectionErrorMessage, ConnectionErrorType, ServerCmd, ServerMessage,
+    },
+    schema,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Parses the JSON and checks that it serializes back to the same value
+fn round_trip<T: Serialize + DeserializeOwned>(json: &str) -> T {
+    let expected: Value = serde_json::from_str(json).unwrap();
+    let msg: T = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_value(&msg).unwrap(), expected, "{json}");
+    msg
+}
+
+#[test]
+fn server_messages_round_trip() {
+    let user = r#""user":{"id":"1000","name":"tester"}"#;
+    for json in [
+        r#"{"id":"","user":null,"cmd":"hello","protocol":1,"commands":["link"],"features":["events"]}"#.to_string(),
+        format!(r#"{{"id":"1",{user},"cmd":"message","text":"Welcome","copy":"!invite"}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"message","text":"Welcome","copy":null}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"game"}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"link","game":1086940,"steam_id":null}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"link","game":1086940,"steam_id":"76561197960265729"}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"kick","guest":3,"user_id":null}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"kick","guest":null,"user_id":"1000"}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"players"}}"#),
+        format!(r#"{{"id":"1",{user},"cmd":"status"}}"#),
+        format!(r#"{{"id":"1",{user},"guild":"500","channel":"600","cmd":"players"}}"#),
+        r#"{"id":"1","user":null,"cmd":"exit"}"#.to_string(),
+    ] {
+        round_trip::<ServerMessage>(&json);
+    }
+}
+
+#[test]
+fn optional_server_fields_may_be_omitted() {
+    let msg: ServerMessage = serde_json::from_str(r#"{"cmd":"link","game":10}"#).unwrap();
+    assert_eq!(msg.id, "");
+    assert!(msg.user.is_none());
+    assert!(matches!(
+        msg.cmd,
+        ServerCmd::Link {
+            game: 10,
+            steam_id: None
+        }
+    ));
+}
+
+#[test]
+fn unknown_server_commands_are_invalid() {
+    let msg: ServerMessage =
+        serde_json::from_str(r#"{"id":"1","user":null,"cmd":"teleport","to":"mars"}"#).unwrap();
+    assert!(matches!(msg.cmd, ServerCmd::Invalid));
+}
+
+#[test]
+fn client_messages_round_trip() {
+    let user = r#"{"id":"1000","name":"tester"}"#;
+    let guest = format!(r#""guest_id":1,"steam_id":"76561197960265729","user":{user}"#);
+    for json in [
+        r#"{"cmd":"hello","protocol":1,"version":"1.0.0","commands":["link"],"os":"linux-x86_64","locale":"en-US","features":["events"]}"#.to_string(),
+        r#"{"id":"1","cmd":"game","game":1086940}"#.to_string(),
+        r#"{"id":"1","cmd":"game","game":1086940,"welcome":"Be nice!"}"#.to_string(),
+        r#"{"id":"1","cmd":"link","url":"https://s.team/p/ABCD-1234","direct":false}"#.to_string(),
+        r#"{"id":"1","cmd":"kick","guests":[1,2]}"#.to_string(),
+        format!(r#"{{"id":"1","cmd":"players","players":[{{{guest},"joined_at":1700000000,"duration":60}}]}}"#),
+        r#"{"id":"1","cmd":"status","version":"1.0.0","os":"windows-x86_64","uptime":10,"connections":2,"reconnects":1,"game":null,"remote_play_together":false,"guests":0,"pending":0}"#.to_string(),
+        r#"{"id":"1","cmd":"error","code":"invalid_cmd"}"#.to_string(),
+        r#"{"id":"1","cmd":"error","code":"quota_exceeded"}"#.to_string(),
+        r#"{"cmd":"offline","reason":"Host is shutting down (SIGTERM)"}"#.to_string(),
+        format!(r#"{{"cmd":"sync","resumed":true,"guests":[{{{guest}}}],"pending":[{{"guest_id":2,"steam_id":null,"user":null}}]}}"#),
+        format!(r#"{{"cmd":"player_joined",{guest}}}"#),
+        format!(r#"{{"cmd":"player_left",{guest}}}"#),
+        r#"{"cmd":"game_changed","game":730,"previous":null}"#.to_string(),
+        r#"{"cmd":"latency","current":12,"average":15}"#.to_string(),
+    ] {
+        round_trip::<ClientMessage>(&json);
+    }
+}
+
+#[test]
+fn connection_errors_round_trip() {
+    let msg = round_trip::<ConnectionErrorMessage>(
+        r#"{"message":"Please update","error":"outdated","required":"1.2.0","download":"https://example.com"}"#,
+    );
+    assert!(matches!(msg.error, ConnectionErrorType::Outdated { .. }));
+
+    let msg: ConnectionErrorMessage =
+        serde_json::from_str(r#"{"message":"Nope","error":"banned"}"#).unwrap();
+    assert!(matches!(msg.error, ConnectionErrorType::Other));
+}
+
+#[test]
+fn schema_covers_every_message() {
+    let schema: Value = serde_json::from_str(&schema::export().unwrap()).unwrap();
+    assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+    assert_eq!(schema["protocol"], capabilities::PROTOCOL_VERSION);
+    for name in ["ServerMessage", "ClientMessage", "ConnectionErrorMessage"] {
+        assert!(schema["definitions"][name].is_object(), "missing {name}");
+        let reference = serde_json::json!({ "$ref": format!("#/definitions/{name}") });
+        assert!(schema["anyOf"].as_array().unwrap().contains(&reference));
+    }
+
+    // Every reference points into the document
+    let text = schema.to_string();
+    for reference in text.split("\"#/definitions/").skip(1) {
+        let name = &reference[..reference.find('"').unwrap()];
+        assert!(schema["definitions"][name].is_object(), "missing {name}");
+    }
+
+    for cmd in [
+        "hello",
+        "message",
+        "game",
+        "link",
+        "kick",
+        "players",
+        "status",
+        "exit",
+        "error",
+        "offline",
+        "sync",
+        "player_joined",
+        "player_left",
+        "game_changed",
+        "latency",
+        "outdated",
+        "quota_exceeded",
+    ] {
+        assert!(text.contains(&format!("\"{cmd}\"")), "missing {cmd}");
+    }
+}
+
+#[test]
+fn schema_command_prints_plain_json() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_remoteplay-inviter"))
+        .arg("--schema")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let schema: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        schema,
+        serde_json::from_str::<Value>(&schema::export().unwrap()).unwrap()
+    );
+}
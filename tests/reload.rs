@@ -0,0 +1,125 @@
+mod common;
+
+use common::temp::TempDir;
+use remoteplay_inviter::{
+    client::Endpoint,
+    config::{Config, LayeredConfig, Source},
+    handlers::Handler,
+    reload::Reloader,
+};
+use std::{fs, path::PathBuf, sync::Arc};
+use steam_stuff::{MockSteamStuff, RemotePlayBackend};
+use tokio::sync::{watch, Mutex};
+
+const CONFIG: &str = r#"
+uuid = "test-uuid"
+url = "ws://127.0.0.1:8080"
+
+[limits]
+max_guests = 2
+"#;
+
+/// Loads the defaults and the file
+fn load(path: PathBuf) -> impl Fn() -> anyhow::Result<LayeredConfig> + Send + 'static {
+    move || {
+        let mut layers = LayeredConfig::default();
+        layers.merge(toml::Table::try_from(Config::default())?, &Source::Default);
+        layers.merge_file(&path)?;
+        Ok(layers)
+    }
+}
+
+struct Setup {
+    _dir: TempDir,
+    path: PathBuf,
+    handler: Arc<Handler>,
+    endpoint: watch::Receiver<Endpoint>,
+    reloader: Reloader<Box<dyn Fn() -> anyhow::Result<LayeredConfig> + Send>>,
+}
+
+fn setup() -> Setup {
+    let dir = TempDir::new();
+    let path = dir.write("config.toml", CONFIG);
+    let load: Box<dyn Fn() -> anyhow::Result<LayeredConfig> + Send> = Box::new(load(path.clone()));
+    let layers = load().unwrap();
+
+    let steam: Arc<Mutex<dyn RemotePlayBackend>> = Arc::new(Mutex::new(MockSteamStuff::new()));
+    let handler = Arc::new(Handler::new(steam, layers.get().unwrap()));
+    let (tx, endpoint) = watch::channel(Endpoint::default());
+    let reloader = Reloader::new(load, vec![path.clone()], layers, 1, handler.clone(), tx);
+    Setup {
+        _dir: dir,
+        path,
+        handler,
+        endpoint,
+        reloader,
+    }
+}
+
+#[tokio::test]
+async fn applies_policy_changes_without_reconnecting() {
+    let mut setup = setup();
+    assert!(setup.reloader.check().is_empty());
+
+    let content = format!("{CONFIG}max_pending_links = 1\n\n[access]\nallow_users = [\"1000\"]\n");
+    fs::write(&setup.path, content).unwrap();
+    assert_eq!(
+        setup.reloader.check(),
+        ["access.allow_users", "limits.max_pending_links"]
+    );
+
+    let config = setup.handler.config();
+    assert_eq!(config.limits.max_guests, Some(2));
+    assert_eq!(config.limits.max_pending_links, Some(1));
+    assert_eq!(config.access.allow_users, ["1000"]);
+    assert!(!setup.endpoint.has_changed().unwrap());
+
+    // Nothing changes until the files do
+    assert!(setup.reloader.check().is_empty());
+}
+
+#[tokio::test]
+async fn reconnects_when_endpoint_changes() {
+    let mut setup = setup();
+
+    fs::write(
+        &setup.path,
+        CONFIG
+            .replace("127.0.0.1:8080", "127.0.0.1:9090")
+            .replace("uuid =", "token = \"new-token\"\nuuid ="),
+    )
+    .unwrap();
+    assert_eq!(setup.reloader.check(), ["token", "url"]);
+
+    assert!(setup.endpoint.has_changed().unwrap());
+    let url = setup.endpoint.borrow_and_update().url.clone();
+    assert!(url.starts_with("ws://127.0.0.1:9090/ws?"));
+    assert!(url.contains("token=new-token"));
+}
+
+#[tokio::test]
+async fn invalid_changes_keep_the_current_configuration() {
+    let mut setup = setup();
+
+    fs::write(&setup.path, "[limits\nmax_guests = 5\n").unwrap();
+    assert!(setup.reloader.check().is_empty());
+    fs::write(
+        &setup.path,
+        CONFIG.replace("max_guests = 2", "max_guests = \"many\""),
+    )
+    .unwrap();
+    assert!(setup.reloader.check().is_empty());
+    fs::write(&setup.path, CONFIG.replace("ws://", "not a url")).unwrap();
+    assert!(setup.reloader.check().is_empty());
+
+    assert_eq!(setup.handler.config().limits.max_guests, Some(2));
+    assert!(!setup.endpoint.has_changed().unwrap());
+
+    // A valid file applies again
+    fs::write(
+        &setup.path,
+        CONFIG.replace("max_guests = 2", "max_guests = 5"),
+    )
+    .unwrap();
+    assert_eq!(setup.reloader.check(), ["limits.max_guests"]);
+}